@@ -0,0 +1,444 @@
+use actix_web::body::{BodySize, MessageBody, ResponseBody};
+use actix_web::dev::{Service, ServiceRequest, ServiceResponse};
+use actix_web::http::header;
+use actix_web::http::HeaderValue;
+use actix_web::Error;
+use brotli2::write::BrotliEncoder;
+use bytes::Bytes;
+use core::task::Context;
+use flate2::write::GzEncoder;
+use futures::future::{poll_fn, Future};
+use futures::task::Poll;
+use std::collections::VecDeque;
+use std::io::Write;
+
+const GZIP_LEVEL: u32 = 6;
+const BROTLI_LEVEL: u32 = 5; // higher levels are too slow for streaming
+const ZSTD_LEVEL: i32 = 3;
+
+/// Compression of streamed responses, configured by `http.compression`.
+#[derive(Debug, Clone, Copy)]
+pub struct CompressionConfig {
+    pub enabled: bool,
+    pub min_size: usize, // responses ending below this size are sent uncompressed
+}
+
+impl CompressionConfig {
+    pub fn from_settings(settings: &config::Config) -> CompressionConfig {
+        CompressionConfig {
+            enabled: settings
+                .get_bool("http.compression.enabled")
+                .unwrap_or(true),
+            min_size: settings
+                .get_int("http.compression.min_size")
+                .map(|size| size.max(0) as usize)
+                .unwrap_or(1024),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Encoding {
+    Zstd,
+    Brotli,
+    Gzip,
+}
+
+impl Encoding {
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Zstd => "zstd",
+            Encoding::Brotli => "br",
+            Encoding::Gzip => "gzip",
+        }
+    }
+
+    /// The supported encoding of an `Accept-Encoding` header with the highest quality.
+    /// On equal quality zstd is preferred over brotli and brotli over gzip.
+    pub fn negotiate(accept_encoding: &str) -> Option<Encoding> {
+        let mut best: Option<(Encoding, f32)> = None;
+        for item in accept_encoding.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let encoding = match parts.next().map(str::to_ascii_lowercase).as_deref() {
+                Some("zstd") => Encoding::Zstd,
+                Some("br") => Encoding::Brotli,
+                Some("gzip") | Some("x-gzip") => Encoding::Gzip,
+                _ => continue,
+            };
+            let quality = parts
+                .find_map(|param| param.strip_prefix("q="))
+                .map(|q| q.parse::<f32>().unwrap_or(0.0))
+                .unwrap_or(1.0);
+            if quality <= 0.0 {
+                continue;
+            }
+            let better = match best {
+                Some((best_encoding, best_quality)) => {
+                    quality > best_quality
+                        || (quality == best_quality && encoding.rank() < best_encoding.rank())
+                }
+                None => true,
+            };
+            if better {
+                best = Some((encoding, quality));
+            }
+        }
+        best.map(|(encoding, _)| encoding)
+    }
+
+    fn rank(self) -> u8 {
+        match self {
+            Encoding::Zstd => 0,
+            Encoding::Brotli => 1,
+            Encoding::Gzip => 2,
+        }
+    }
+}
+
+enum Encoder {
+    Gzip(GzEncoder<Vec<u8>>),
+    Brotli(BrotliEncoder<Vec<u8>>),
+    Zstd(zstd::stream::write::Encoder<Vec<u8>>),
+}
+
+impl Encoder {
+    fn new(encoding: Encoding) -> std::io::Result<Encoder> {
+        Ok(match encoding {
+            Encoding::Gzip => Encoder::Gzip(GzEncoder::new(
+                Vec::new(),
+                flate2::Compression::new(GZIP_LEVEL),
+            )),
+            Encoding::Brotli => Encoder::Brotli(BrotliEncoder::new(Vec::new(), BROTLI_LEVEL)),
+            Encoding::Zstd => {
+                Encoder::Zstd(zstd::stream::write::Encoder::new(Vec::new(), ZSTD_LEVEL)?)
+            }
+        })
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match self {
+            Encoder::Gzip(encoder) => encoder,
+            Encoder::Brotli(encoder) => encoder,
+            Encoder::Zstd(encoder) => encoder,
+        }
+    }
+
+    /// Compressed output produced so far.
+    fn take(&mut self) -> Bytes {
+        let output = match self {
+            Encoder::Gzip(encoder) => encoder.get_mut(),
+            Encoder::Brotli(encoder) => encoder.get_mut(),
+            Encoder::Zstd(encoder) => encoder.get_mut(),
+        };
+        Bytes::from(std::mem::take(output))
+    }
+
+    fn finish(self) -> std::io::Result<Bytes> {
+        let output = match self {
+            Encoder::Gzip(encoder) => encoder.finish()?,
+            Encoder::Brotli(encoder) => encoder.finish()?,
+            Encoder::Zstd(encoder) => encoder.finish()?,
+        };
+        Ok(Bytes::from(output))
+    }
+}
+
+/// Body continuing a response after its first chunks were read to decide about compression.
+/// The compressor is flushed whenever the response has no further chunks at hand, e.g. while
+/// watching for new lines, so clients receive every line as soon as it is read.
+pub struct EncodedBody<B> {
+    prefetched: VecDeque<Result<Bytes, Error>>,
+    body: Option<B>, // None after the end of the body
+    encoder: Option<Encoder>,
+    unflushed: bool,
+}
+
+impl<B: MessageBody> EncodedBody<B> {
+    fn next_chunk(&mut self, cx: &mut Context) -> Poll<Option<Result<Bytes, Error>>> {
+        if let Some(chunk) = self.prefetched.pop_front() {
+            return Poll::Ready(Some(chunk));
+        }
+        match &mut self.body {
+            Some(body) => {
+                let polled = body.poll_next(cx);
+                if let Poll::Ready(None) = polled {
+                    self.body = None;
+                }
+                polled
+            }
+            None => Poll::Ready(None),
+        }
+    }
+}
+
+impl<B: MessageBody> MessageBody for EncodedBody<B> {
+    fn size(&self) -> BodySize {
+        match (&self.encoder, &self.body) {
+            (None, Some(body)) if self.prefetched.is_empty() => body.size(),
+            _ => BodySize::Stream,
+        }
+    }
+
+    fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Bytes, Error>>> {
+        if self.encoder.is_none() {
+            return self.next_chunk(cx);
+        }
+        loop {
+            match self.next_chunk(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    let encoder = self.encoder.as_mut().unwrap();
+                    encoder.writer().write_all(&chunk)?;
+                    self.unflushed = true;
+                    let output = encoder.take();
+                    if !output.is_empty() {
+                        return Poll::Ready(Some(Ok(output)));
+                    }
+                }
+                Poll::Ready(Some(Err(e))) => return Poll::Ready(Some(Err(e))),
+                Poll::Ready(None) => {
+                    return match self.encoder.take().map(Encoder::finish) {
+                        Some(output) => Poll::Ready(Some(output.map_err(Error::from))),
+                        None => Poll::Ready(None),
+                    };
+                }
+                Poll::Pending if self.unflushed => {
+                    let encoder = self.encoder.as_mut().unwrap();
+                    encoder.writer().flush()?;
+                    self.unflushed = false;
+                    return Poll::Ready(Some(Ok(encoder.take())));
+                }
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+/// Read the body until it reaches the minimum size for compression, ends or has to wait for further chunks.
+/// Returns the chunks read and whether the body is worth compressing, which an open live stream always is.
+async fn prefetch<B: MessageBody>(
+    body: &mut B,
+    min_size: usize,
+) -> (VecDeque<Result<Bytes, Error>>, bool, bool) {
+    let mut chunks = VecDeque::new();
+    let mut size = 0;
+    let (compress, ended) = poll_fn(|cx| loop {
+        match body.poll_next(cx) {
+            Poll::Ready(Some(Ok(chunk))) => {
+                size += chunk.len();
+                chunks.push_back(Ok(chunk));
+                if size >= min_size {
+                    return Poll::Ready((true, false));
+                }
+            }
+            Poll::Ready(Some(Err(e))) => {
+                chunks.push_back(Err(e));
+                return Poll::Ready((false, false));
+            }
+            Poll::Ready(None) => return Poll::Ready((false, true)),
+            Poll::Pending => return Poll::Ready((true, false)),
+        }
+    })
+    .await;
+    (chunks, compress, ended)
+}
+
+/// Compress successful streamed responses in an encoding accepted by the client.
+/// Responses with a known size or an encoding of their own are left as they are.
+pub fn compress<S, B>(
+    req: ServiceRequest,
+    service: &mut S,
+    config: CompressionConfig,
+) -> impl Future<Output = Result<ServiceResponse<EncodedBody<ResponseBody<B>>>, Error>>
+where
+    S: Service<Request = ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    B: MessageBody,
+{
+    let encoding = req
+        .headers()
+        .get(header::ACCEPT_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .and_then(Encoding::negotiate);
+    let response = service.call(req);
+
+    async move {
+        let mut res = response.await?;
+
+        let mut body = res.take_body();
+        let compressible = config.enabled
+            && res.status().is_success()
+            && body.size() == BodySize::Stream
+            && !res.headers().contains_key(header::CONTENT_ENCODING);
+        // caches must not serve a response to clients accepting other encodings, even if it is not compressed
+        if compressible {
+            res.headers_mut()
+                .append(header::VARY, HeaderValue::from_static("accept-encoding"));
+        }
+        let (prefetched, ended, encoder) = match encoding.filter(|_| compressible) {
+            Some(encoding) => {
+                let (prefetched, compress, ended) = prefetch(&mut body, config.min_size).await;
+                let encoder = if compress {
+                    Some(Encoder::new(encoding)?)
+                } else {
+                    None
+                };
+                if encoder.is_some() {
+                    let headers = res.headers_mut();
+                    headers.insert(
+                        header::CONTENT_ENCODING,
+                        HeaderValue::from_static(encoding.name()),
+                    );
+                    headers.remove(header::CONTENT_LENGTH);
+                }
+                (prefetched, ended, encoder)
+            }
+            None => (VecDeque::new(), false, None),
+        };
+
+        Ok(res.map_body(move |_, _| {
+            ResponseBody::Body(EncodedBody {
+                prefetched,
+                body: if ended { None } else { Some(body) },
+                encoder,
+                unflushed: false,
+            })
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::compression::{compress, CompressionConfig, EncodedBody, Encoder, Encoding};
+    use actix_web::body::BodyStream;
+    use actix_web::body::MessageBody;
+    use actix_web::http::header;
+    use actix_web::{test, web, App, Error, HttpResponse};
+    use async_std::task;
+    use bytes::Bytes;
+    use futures::future::poll_fn;
+    use futures::stream;
+    use futures::stream::StreamExt;
+    use futures::task::Poll;
+    use std::collections::VecDeque;
+    use std::io::Read;
+
+    const LINES: &str =
+        "2019-01-01 10:00:01 DEBUG demo0line1\n2019-01-01 10:00:02 DEBUG demo0line2\n";
+
+    fn encoded_body<S>(source: S, encoding: Encoding) -> EncodedBody<BodyStream<S, Error>>
+    where
+        S: futures::Stream<Item = Result<Bytes, Error>> + Unpin,
+    {
+        EncodedBody {
+            prefetched: VecDeque::new(),
+            body: Some(BodyStream::new(source)),
+            encoder: Some(Encoder::new(encoding).unwrap()),
+            unflushed: false,
+        }
+    }
+
+    fn decode(encoding: Encoding, data: &[u8]) -> String {
+        let mut decoded = String::new();
+        match encoding {
+            Encoding::Gzip => flate2::read::GzDecoder::new(data)
+                .read_to_string(&mut decoded)
+                .unwrap(),
+            Encoding::Brotli => brotli2::read::BrotliDecoder::new(data)
+                .read_to_string(&mut decoded)
+                .unwrap(),
+            Encoding::Zstd => zstd::stream::read::Decoder::new(data)
+                .unwrap()
+                .read_to_string(&mut decoded)
+                .unwrap(),
+        };
+        decoded
+    }
+
+    #[test]
+    fn test_negotiate() {
+        assert_eq!(Encoding::negotiate("gzip, deflate"), Some(Encoding::Gzip));
+        assert_eq!(
+            Encoding::negotiate("gzip, deflate, br"),
+            Some(Encoding::Brotli)
+        );
+        assert_eq!(Encoding::negotiate("br;q=0.5, gzip"), Some(Encoding::Gzip));
+        assert_eq!(Encoding::negotiate("zstd, br, gzip"), Some(Encoding::Zstd));
+        assert_eq!(Encoding::negotiate("gzip;q=0, identity"), None);
+        assert_eq!(Encoding::negotiate(""), None);
+    }
+
+    #[test]
+    fn test_round_trip() {
+        for encoding in &[Encoding::Gzip, Encoding::Brotli, Encoding::Zstd] {
+            let chunks = LINES
+                .split_inclusive('\n')
+                .map(|line| Ok(Bytes::from(line.to_string())))
+                .collect::<Vec<_>>();
+            let mut body = encoded_body(stream::iter(chunks), *encoding);
+            let mut compressed = Vec::new();
+            while let Some(chunk) = task::block_on(poll_fn(|cx| body.poll_next(cx))) {
+                compressed.extend_from_slice(&chunk.unwrap());
+            }
+            assert_eq!(decode(*encoding, &compressed), LINES);
+        }
+    }
+
+    #[test]
+    fn test_flush_while_waiting() {
+        for encoding in &[Encoding::Gzip, Encoding::Brotli, Encoding::Zstd] {
+            let source = stream::iter(vec![Ok(Bytes::from(LINES))]).chain(stream::pending());
+            let mut body = encoded_body(source, *encoding);
+            let flushed = task::block_on(poll_fn(|cx| {
+                let mut flushed = Vec::new();
+                while let Poll::Ready(Some(chunk)) = body.poll_next(cx) {
+                    flushed.extend_from_slice(&chunk.unwrap());
+                }
+                Poll::Ready(flushed)
+            }));
+            // the stream is not finished, but the lines read so far can be decoded
+            let mut decoded = vec![0; LINES.len()];
+            let read = match encoding {
+                Encoding::Gzip => flate2::read::GzDecoder::new(&flushed[..])
+                    .read_exact(&mut decoded)
+                    .is_ok(),
+                Encoding::Brotli => brotli2::read::BrotliDecoder::new(&flushed[..])
+                    .read_exact(&mut decoded)
+                    .is_ok(),
+                Encoding::Zstd => zstd::stream::read::Decoder::new(&flushed[..])
+                    .unwrap()
+                    .read_exact(&mut decoded)
+                    .is_ok(),
+            };
+            assert!(read, "{:?}", encoding);
+            assert_eq!(decoded, LINES.as_bytes());
+        }
+    }
+    #[actix_rt::test]
+    async fn test_vary_without_compression() {
+        let config = CompressionConfig {
+            enabled: true,
+            min_size: 1024,
+        };
+        let mut app = test::init_service(
+            App::new()
+                .wrap_fn(move |req, srv| compress(req, srv, config))
+                .route(
+                    "/",
+                    web::get().to(|| {
+                        HttpResponse::Ok().streaming(stream::once(async {
+                            Ok::<_, Error>(Bytes::from_static(LINES.as_bytes()))
+                        }))
+                    }),
+                ),
+        )
+        .await;
+        // too small to be compressed and without accepted encoding
+        for accept_encoding in &["gzip", "identity"] {
+            let req = test::TestRequest::get()
+                .header(header::ACCEPT_ENCODING, *accept_encoding)
+                .to_request();
+            let res = test::call_service(&mut app, req).await;
+            assert_eq!(res.headers().get(header::VARY).unwrap(), "accept-encoding");
+            assert!(res.headers().get(header::CONTENT_ENCODING).is_none());
+        }
+    }
+}
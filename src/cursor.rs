@@ -0,0 +1,96 @@
+use crate::data::ApplicationError;
+
+/// Position directly after a log line of a single source, or at its start when reading newest first.
+/// The file is identified by device and inode, so the position stays valid when the file is renamed.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
+pub struct SourceCursor {
+    #[serde(rename = "s")]
+    pub source: String,
+    #[serde(rename = "d")]
+    pub dev: u64,
+    #[serde(rename = "i")]
+    pub inode: u64,
+    #[serde(rename = "o")]
+    pub offset: u64, // byte offset after (or at) the line, uncompressed for gzip files
+    #[serde(rename = "l", default, skip_serializing_if = "Option::is_none")]
+    pub line: Option<u64>, // number of the line within the file, if known
+    #[serde(rename = "r", default, skip_serializing_if = "is_false")]
+    pub desc: bool, // read newest first, so the offset is at the start of the line
+}
+
+fn is_false(b: &bool) -> bool {
+    !*b
+}
+
+/// Opaque resume token, composed of the cursors of all sources of a (merged) query.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone, Default)]
+pub struct Cursor {
+    sources: Vec<SourceCursor>,
+}
+
+impl Cursor {
+    pub fn decode(encoded: &str) -> Result<Cursor, ApplicationError> {
+        let invalid = || ApplicationError::InvalidQuery(format!("Invalid cursor: {}", encoded));
+        let json =
+            base64::decode_config(encoded, base64::URL_SAFE_NO_PAD).map_err(|_| invalid())?;
+        let sources: Vec<SourceCursor> = serde_json::from_slice(&json).map_err(|_| invalid())?;
+        Ok(Cursor { sources })
+    }
+
+    pub fn encode(&self) -> String {
+        let json = serde_json::to_vec(&self.sources).unwrap_or_default();
+        base64::encode_config(&json, base64::URL_SAFE_NO_PAD)
+    }
+
+    /// Replace the position of the cursor's source with the given one.
+    pub fn advance(&mut self, source_cursor: &SourceCursor) {
+        match self
+            .sources
+            .iter_mut()
+            .find(|c| c.source == source_cursor.source)
+        {
+            Some(c) => *c = source_cursor.clone(),
+            None => self.sources.push(source_cursor.clone()),
+        }
+    }
+
+    pub fn get(&self, source: &str) -> Option<&SourceCursor> {
+        self.sources.iter().find(|c| c.source == source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cursor::{Cursor, SourceCursor};
+
+    fn source_cursor(source: &str, offset: u64) -> SourceCursor {
+        SourceCursor {
+            source: source.to_string(),
+            dev: 2049,
+            inode: 1234,
+            offset,
+            line: None,
+            desc: false,
+        }
+    }
+
+    #[test]
+    fn test_encode_decode() {
+        let mut cursor = Cursor::default();
+        cursor.advance(&source_cursor("a", 10));
+        cursor.advance(&source_cursor("b", 20));
+        cursor.advance(&source_cursor("a", 30));
+
+        let decoded = Cursor::decode(&cursor.encode()).unwrap();
+        assert_eq!(decoded, cursor);
+        assert_eq!(decoded.get("a"), Some(&source_cursor("a", 30)));
+        assert_eq!(decoded.get("b"), Some(&source_cursor("b", 20)));
+        assert_eq!(decoded.get("c"), None);
+    }
+
+    #[test]
+    fn test_decode_invalid() {
+        assert!(Cursor::decode("not a cursor").is_err());
+        assert!(Cursor::decode("e30").is_err()); // valid base64 of "{}"
+    }
+}
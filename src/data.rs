@@ -1,8 +1,13 @@
+use crate::cursor::Cursor;
+use crate::cursor::SourceCursor;
+use crate::log_dedupe::DedupeMode;
+use crate::template::Template;
 use config;
 use derive_more::Display;
 use futures::stream::LocalBoxStream;
 use grok;
 use regex::Regex;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 #[derive(Debug, Display)]
@@ -10,9 +15,21 @@ pub enum ApplicationError {
     // indicates that a requested log source is not configured
     #[display(fmt = "Source not found")]
     SourceNotFound,
+    // indicates that a requested saved query is not configured
+    #[display(fmt = "Query not found")]
+    QueryNotFound,
+    // indicates that a requested file is not one of the files of a source
+    #[display(fmt = "File not found")]
+    FileNotFound,
     // indicates that a requested log source is configured but cannot be read
     #[display(fmt = "Failed to read source")]
     FailedToReadSource,
+    // indicates that the file a cursor points into is gone, e.g. compressed by log rotation
+    #[display(fmt = "File of cursor no longer exists")]
+    CursorExpired,
+    // indicates that the query parameters of a request are malformed
+    #[display(fmt = "{}", _0)]
+    InvalidQuery(String),
 }
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Eq, Clone)]
@@ -20,12 +37,89 @@ pub struct ParsedLine {
     pub timestamp: u128,
     pub loglevel: Option<String>,
     pub message: String,
+    // further named captures of the line pattern
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub fields: BTreeMap<String, String>,
+}
+
+impl ParsedLine {
+    /// Value of a field by name, including the predefined loglevel and message.
+    pub fn field(&self, name: &str) -> Option<&str> {
+        match name {
+            "loglevel" => self.loglevel.as_deref(),
+            "message" => Some(&self.message),
+            _ => self.fields.get(name).map(|v| v.as_str()),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Eq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum MarkerReason {
+    Sample,
+    MaxRate,
+    MaxLines,
+    Dropped, // a follower fell behind the followed file
+    Failed,  // a source failed while reading, its remaining entries are missing
+}
+
+impl MarkerReason {
+    /// Text of the marker entry.
+    pub fn message(self) -> &'static str {
+        match self {
+            MarkerReason::Sample => "Output is sampled, entries were left out.",
+            MarkerReason::MaxRate => "Output exceeded max_rate, entries were left out.",
+            MarkerReason::MaxLines => "Output reached max_lines, further entries are left out.",
+            MarkerReason::Dropped => "Output fell behind the followed file, entries were dropped.",
+            MarkerReason::Failed => "A tentacle failed while retrieving the log.",
+        }
+    }
+}
+
+/// Describes entries which were left out of the output, e.g. because of a limit.
+#[derive(Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct Marker {
+    pub reason: MarkerReason,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<u64>, // None if the number of left out entries is unknown
+}
+
+/// Describes a run of consecutive entries collapsed into a single one.
+#[derive(Serialize, Debug, PartialEq, Eq, Clone)]
+pub struct Repeat {
+    #[serde(rename = "repeat_count")]
+    pub count: u64,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub first_ts: u128,
+    #[serde(serialize_with = "serialize_timestamp")]
+    pub last_ts: u128,
+}
+
+// millisecond timestamps fit into u64, which unlike u128 is supported by all serialization formats
+fn serialize_timestamp<S: serde::Serializer>(
+    timestamp: &u128,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_u64(*timestamp as u64)
+}
+
+/// Where an entry was read from.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Origin {
+    pub source: Arc<String>,
+    pub file: Arc<String>,
+    pub line: Option<u64>, // line number within the file, if known
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub struct StreamEntry {
     pub line: String,
     pub parsed_line: ParsedLine,
+    pub cursor: Option<SourceCursor>,
+    pub context: bool, // entry does not match the filter, but surrounds a matching entry
+    pub marker: Option<Marker>, // entry is no log line, but a note about left out entries
+    pub repeat: Option<Repeat>, // entry stands for a run of repeated entries
+    pub origin: Option<Origin>, // None for entries not read from a source, like markers
 }
 
 impl StreamEntry {
@@ -36,30 +130,84 @@ impl StreamEntry {
 
 pub type LogStream = LocalBoxStream<'static, Result<StreamEntry, ApplicationError>>;
 
+/// Order of the entries in the output by timestamp.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    Asc,
+    Desc, // newest first
+}
+
+impl Order {
+    pub fn parse(order: &str) -> Result<Order, ApplicationError> {
+        match order {
+            "asc" => Ok(Order::Asc),
+            "desc" => Ok(Order::Desc),
+            o => Err(ApplicationError::InvalidQuery(format!(
+                "Unsupported order: {}",
+                o
+            ))),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct LogQueryContext {
     pub from_ms: u128,
+    pub to_ms: Option<u128>,
     pub loglevels: Option<Vec<String>>,
     pub watch: Option<bool>,
+    pub after: Option<Cursor>,
+    pub before_lines: usize,
+    pub after_lines: usize,
+    pub dedupe: Option<DedupeMode>,
+    pub order: Order,
 }
 
 impl LogQueryContext {
-    /// Check the filter against the log line.
+    /// Check if the log line lies within the requested time range.
+    pub fn in_range(&self, parsed_line: &ParsedLine) -> bool {
+        let before_end = match self.to_ms {
+            Some(to_ms) => parsed_line.timestamp < to_ms,
+            None => true,
+        };
+        parsed_line.timestamp >= self.from_ms && before_end
+    }
+
+    /// Check the filter against the log line, ignoring the time range.
     /// If the logline matches the filter and should be included within the output
     /// the this returns true.
-    pub fn matches(&self, parsed_line: &ParsedLine) -> bool {
-        if parsed_line.timestamp >= self.from_ms {
-            match &self.loglevels {
-                Some(filter) => match &parsed_line.loglevel {
-                    Some(loglvl) => filter.iter().any(|f| f == loglvl),
-                    None => false,
-                },
-                None => true, // no filter, everything matches
-            }
+    pub fn matches_filter(&self, parsed_line: &ParsedLine) -> bool {
+        match &self.loglevels {
+            Some(filter) => match &parsed_line.loglevel {
+                Some(loglvl) => filter.iter().any(|f| f == loglvl),
+                None => false,
+            },
+            None => true, // no filter, everything matches
+        }
+    }
+
+    /// Whether the log line is delivered, as a match (true) or as a possible context line (false).
+    pub fn select(&self, parsed_line: &ParsedLine) -> Option<bool> {
+        if !self.in_range(parsed_line) {
+            return None;
+        }
+        let is_match = self.matches_filter(parsed_line);
+        if is_match || self.with_context_lines() {
+            Some(is_match)
         } else {
-            false
+            None
         }
     }
+
+    /// Indicates if non-matching entries around matches are requested.
+    pub fn with_context_lines(&self) -> bool {
+        self.before_lines > 0 || self.after_lines > 0
+    }
+
+    /// The position after which the given source should continue, if the query resumes a stream.
+    pub fn resume_point(&self, source: &str) -> Option<&SourceCursor> {
+        self.after.as_ref().and_then(|cursor| cursor.get(source))
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -77,11 +225,15 @@ pub enum LogSource {
         id: String,
         file_pattern: Regex,
         line_pattern: LinePattern,
+        labels: BTreeMap<String, String>,
+        template: Option<Template>, // default of the text output
     },
     Journal {
         id: String,
         unit: String,
         line_pattern: LinePattern,
+        labels: BTreeMap<String, String>,
+        template: Option<Template>, // default of the text output
     },
 }
 
@@ -134,6 +286,24 @@ impl LogSourceBuilder {
             .into_str()?;
         let timezone: chrono_tz::Tz = timezone.parse().unwrap();
 
+        let labels = match file_map.get("labels") {
+            Some(labels) => labels
+                .clone()
+                .into_table()?
+                .into_iter()
+                .map(|(key, value)| value.into_str().map(|value| (key, value)))
+                .collect::<Result<BTreeMap<String, String>, config::ConfigError>>()?,
+            None => BTreeMap::new(),
+        };
+
+        let template = match file_map.get("template") {
+            Some(template) => Some(
+                Template::parse(&template.clone().into_str()?)
+                    .map_err(|e| config::ConfigError::Message(e.to_string()))?,
+            ),
+            None => None,
+        };
+
         let srctype = file_map
             .get("type")
             .ok_or(config::ConfigError::NotFound("type".to_string()))?
@@ -158,6 +328,8 @@ impl LogSourceBuilder {
                         timezone,
                         syslog_ts,
                     },
+                    labels,
+                    template,
                 })
             }
             "file" => {
@@ -178,6 +350,8 @@ impl LogSourceBuilder {
                         timezone,
                         syslog_ts,
                     },
+                    labels,
+                    template,
                 })
             }
             e => Err(config::ConfigError::Message(format!("Unknown type: {}", e))),
@@ -186,3 +360,70 @@ impl LogSourceBuilder {
         logsource
     }
 }
+
+/// A named query of the config, with the query parameters of the content endpoints it fixes.
+#[derive(Debug, Clone)]
+pub struct SavedQuery {
+    pub name: String,
+    pub parameters: BTreeMap<String, String>,
+}
+
+pub struct SavedQueryBuilder;
+
+impl SavedQueryBuilder {
+    pub fn create(value: &config::Value) -> Result<SavedQuery, config::ConfigError> {
+        let mut query_map = value.clone().into_table()?;
+
+        let name = query_map
+            .remove("name")
+            .ok_or(config::ConfigError::NotFound("name".to_string()))?
+            .into_str()?;
+        // lists, e.g. of loglevels, are given comma separated like in the url
+        let parameters = query_map
+            .into_iter()
+            .map(|(key, value)| {
+                let value = match value.clone().into_array() {
+                    Ok(values) => values
+                        .into_iter()
+                        .map(|v| v.into_str())
+                        .collect::<Result<Vec<String>, config::ConfigError>>()?
+                        .join(","),
+                    Err(_) => value.into_str()?,
+                };
+                Ok((key, value))
+            })
+            .collect::<Result<BTreeMap<String, String>, config::ConfigError>>()?;
+
+        Ok(SavedQuery { name, parameters })
+    }
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    use crate::data::{Origin, ParsedLine, StreamEntry};
+    use std::sync::Arc;
+
+    /// An error entry of line 3 of `demo.log` with a `thread` capture, for the tests of the output formats.
+    pub fn entry(message: &str) -> StreamEntry {
+        StreamEntry {
+            line: format!("2019-01-01 10:00:01 ERROR {}", message),
+            parsed_line: ParsedLine {
+                timestamp: 1_546_333_201_000,
+                loglevel: Some("ERROR".to_string()),
+                message: message.to_string(),
+                fields: vec![("thread".to_string(), "main".to_string())]
+                    .into_iter()
+                    .collect(),
+            },
+            cursor: None,
+            context: false,
+            marker: None,
+            repeat: None,
+            origin: Some(Origin {
+                source: Arc::new("demo".to_string()),
+                file: Arc::new("demo.log".to_string()),
+                line: Some(3),
+            }),
+        }
+    }
+}
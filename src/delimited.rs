@@ -0,0 +1,130 @@
+use crate::data::StreamEntry;
+use crate::util;
+use bytes::Bytes;
+use std::borrow::Cow;
+
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3fZ";
+
+/// Separator between the values of a row.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Delimiter {
+    Comma, // comma separated values
+    Tab,   // tab separated values
+}
+
+impl Delimiter {
+    fn as_char(self) -> char {
+        match self {
+            Delimiter::Comma => ',',
+            Delimiter::Tab => '\t',
+        }
+    }
+}
+
+/// Quote a value containing the delimiter, quotes or line breaks, doubling the contained quotes.
+fn quote(value: &str, delimiter: Delimiter) -> Cow<'_, str> {
+    if value.contains(|c| c == delimiter.as_char() || c == '"' || c == '\n' || c == '\r') {
+        Cow::Owned(format!("\"{}\"", value.replace('"', "\"\"")))
+    } else {
+        Cow::Borrowed(value)
+    }
+}
+
+/// Rows of delimiter separated values with a header row naming the columns.
+/// Columns are `timestamp`, `loglevel`, `message`, `raw`, `source`, `file`, `line` or captures of the line pattern.
+pub struct DelimitedFormat {
+    columns: Vec<String>,
+    delimiter: Delimiter,
+}
+
+impl DelimitedFormat {
+    pub fn new(columns: Vec<String>, delimiter: Delimiter) -> DelimitedFormat {
+        DelimitedFormat { columns, delimiter }
+    }
+
+    // rows end with CRLF as in RFC 4180
+    fn row<'a, I>(&self, values: I) -> Bytes
+    where
+        I: Iterator<Item = Cow<'a, str>>,
+    {
+        let mut row = String::new();
+        for (idx, value) in values.enumerate() {
+            if idx > 0 {
+                row.push(self.delimiter.as_char());
+            }
+            row.push_str(&quote(&value, self.delimiter));
+        }
+        row.push_str("\r\n");
+        Bytes::from(row)
+    }
+
+    pub fn header(&self) -> Bytes {
+        self.row(self.columns.iter().map(|c| Cow::Borrowed(c.as_str())))
+    }
+
+    /// The row of an entry, with the columns in the order of the header.
+    /// The timestamp column shows the given time, which continuation lines inherit from the line they continue.
+    pub fn entry(&self, entry: &StreamEntry, timestamp: u128) -> Bytes {
+        let origin = entry.origin.as_ref();
+        self.row(self.columns.iter().map(|column| {
+            match column.as_str() {
+                "timestamp" => Cow::Owned(util::format_timestamp_ms(timestamp, TIMESTAMP_FORMAT)),
+                "raw" => Cow::Borrowed(entry.line.as_str()),
+                "source" => Cow::Borrowed(origin.map(|o| o.source.as_str()).unwrap_or("")),
+                "file" => Cow::Borrowed(origin.map(|o| o.file.as_str()).unwrap_or("")),
+                "line" => Cow::Owned(
+                    origin
+                        .and_then(|o| o.line)
+                        .map(|line| line.to_string())
+                        .unwrap_or_default(),
+                ),
+                name => Cow::Borrowed(entry.parsed_line.field(name).unwrap_or("")),
+            }
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::fixtures::entry;
+    use crate::delimited::{DelimitedFormat, Delimiter};
+
+    fn format(columns: &[&str], delimiter: Delimiter) -> DelimitedFormat {
+        DelimitedFormat::new(columns.iter().map(|c| c.to_string()).collect(), delimiter)
+    }
+
+    #[test]
+    fn test_csv() {
+        let csv = format(
+            &["timestamp", "loglevel", "message", "thread"],
+            Delimiter::Comma,
+        );
+        assert_eq!(csv.header(), "timestamp,loglevel,message,thread\r\n");
+        let e = entry("failed, retrying");
+        assert_eq!(
+            csv.entry(&e, e.parsed_line.timestamp),
+            "2019-01-01T09:00:01.000Z,ERROR,\"failed, retrying\",main\r\n"
+        );
+        let e = entry("unexpected \"token\"\nat line 1");
+        assert_eq!(
+            csv.entry(&e, 0),
+            "1970-01-01T00:00:00.000Z,ERROR,\"unexpected \"\"token\"\"\nat line 1\",main\r\n"
+        );
+    }
+
+    #[test]
+    fn test_tsv() {
+        let tsv = format(
+            &["source", "file", "line", "pid", "message"],
+            Delimiter::Tab,
+        );
+        assert_eq!(tsv.header(), "source\tfile\tline\tpid\tmessage\r\n");
+        let e = entry("failed, retrying");
+        assert_eq!(
+            tsv.entry(&e, 0),
+            "demo\tdemo.log\t3\t\tfailed, retrying\r\n"
+        );
+        let e = entry("a\tb");
+        assert_eq!(tsv.entry(&e, 0), "demo\tdemo.log\t3\t\t\"a\tb\"\r\n");
+    }
+}
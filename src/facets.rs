@@ -0,0 +1,280 @@
+use crate::data::ApplicationError;
+use crate::data::ParsedLine;
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+// number of counters kept per requested top value, more counters make the result more accurate
+const COUNTERS_PER_VALUE: usize = 10;
+const MIN_COUNTERS: usize = 100;
+
+// upper bound of the requested top values, which keeps the counters of a facet bounded
+pub const MAX_TOP: usize = 1000;
+
+// upper bound of the requested fields, each of which keeps its own counters
+pub const MAX_FIELDS: usize = 20;
+
+#[derive(Serialize, Debug, PartialEq)]
+pub struct ValueCount {
+    pub value: String,
+    pub count: u64,
+    // upper bound of the overestimation of count
+    #[serde(skip_serializing_if = "is_zero")]
+    pub error: u64,
+}
+
+fn is_zero(n: &u64) -> bool {
+    *n == 0
+}
+
+/// Approximate top values of a field, using the space saving algorithm:
+/// if all counters are in use, the smallest one is taken over by the new value.
+/// Memory is bounded by the number of counters, counts of frequent values stay exact
+/// or are overestimated by at most `error`.
+/// The values are also kept grouped by count (the stream summary), so the smallest counter
+/// is found without scanning all of them.
+#[derive(Debug)]
+pub struct TopValues {
+    capacity: usize,
+    counters: HashMap<String, (u64, u64)>, // value -> (count, error)
+    by_count: BTreeMap<u64, HashSet<String>>,
+    missing: u64,
+    evicted: bool,
+}
+
+impl TopValues {
+    pub fn new(top: usize) -> Result<TopValues, ApplicationError> {
+        let capacity = top
+            .checked_mul(COUNTERS_PER_VALUE)
+            .filter(|_| top <= MAX_TOP)
+            .ok_or_else(|| {
+                ApplicationError::InvalidQuery(format!(
+                    "At most {} top values can be requested: {}",
+                    MAX_TOP, top
+                ))
+            })?;
+        let capacity = std::cmp::max(capacity, MIN_COUNTERS);
+        Ok(TopValues {
+            capacity,
+            counters: HashMap::with_capacity(capacity),
+            by_count: BTreeMap::new(),
+            missing: 0,
+            evicted: false,
+        })
+    }
+
+    pub fn add(&mut self, value: Option<&str>) {
+        let value = match value {
+            Some(value) => value,
+            None => {
+                self.missing += 1;
+                return;
+            }
+        };
+        if let Some((count, _)) = self.counters.get_mut(value) {
+            *count += 1;
+            let count = *count;
+            self.unlink(value, count - 1);
+            self.link(value, count);
+        } else if self.counters.len() < self.capacity {
+            self.counters.insert(value.to_string(), (1, 0));
+            self.link(value, 1);
+        } else if let Some((min_count, min_value)) = self.smallest() {
+            self.unlink(&min_value, min_count);
+            self.counters.remove(&min_value);
+            self.counters
+                .insert(value.to_string(), (min_count + 1, min_count));
+            self.link(value, min_count + 1);
+            self.evicted = true;
+        }
+    }
+
+    fn smallest(&self) -> Option<(u64, String)> {
+        let (count, values) = self.by_count.iter().next()?;
+        values.iter().next().map(|value| (*count, value.clone()))
+    }
+
+    fn link(&mut self, value: &str, count: u64) {
+        self.by_count
+            .entry(count)
+            .or_default()
+            .insert(value.to_string());
+    }
+
+    fn unlink(&mut self, value: &str, count: u64) {
+        if let Some(values) = self.by_count.get_mut(&count) {
+            values.remove(value);
+            if values.is_empty() {
+                self.by_count.remove(&count);
+            }
+        }
+    }
+
+    /// Number of entries without value for the field.
+    pub fn missing(&self) -> u64 {
+        self.missing
+    }
+
+    /// Indicates if counts may be overestimated, because there were more distinct values than counters.
+    pub fn approximate(&self) -> bool {
+        self.evicted
+    }
+
+    /// The `n` values with the highest counts, ordered by count descending and value.
+    pub fn top(&self, n: usize) -> Vec<ValueCount> {
+        let mut values: Vec<ValueCount> = self
+            .counters
+            .iter()
+            .map(|(value, (count, error))| ValueCount {
+                value: value.clone(),
+                count: *count,
+                error: *error,
+            })
+            .collect();
+        values.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.value.cmp(&b.value)));
+        values.truncate(n);
+        values
+    }
+}
+
+/// Top values for a set of fields.
+/// Lines without timestamp (e.g. continuation lines of multiline messages) are not counted.
+#[derive(Debug)]
+pub struct Facets {
+    top: usize,
+    total: u64,
+    fields: Vec<(String, TopValues)>,
+}
+
+impl Facets {
+    /// Facets of the given fields, a field given more than once is counted once.
+    pub fn new(fields: &[String], top: usize) -> Result<Facets, ApplicationError> {
+        let mut distinct: Vec<&String> = Vec::new();
+        for field in fields {
+            if !distinct.contains(&field) {
+                distinct.push(field);
+            }
+        }
+        if distinct.len() > MAX_FIELDS {
+            return Err(ApplicationError::InvalidQuery(format!(
+                "At most {} facet fields can be requested: {}",
+                MAX_FIELDS,
+                distinct.len()
+            )));
+        }
+        Ok(Facets {
+            top,
+            total: 0,
+            fields: distinct
+                .into_iter()
+                .map(|f| TopValues::new(top).map(|values| (f.clone(), values)))
+                .collect::<Result<_, _>>()?,
+        })
+    }
+
+    pub fn add(&mut self, parsed_line: &ParsedLine) {
+        if parsed_line.timestamp == 0 {
+            return;
+        }
+        self.total += 1;
+        for (field, values) in self.fields.iter_mut() {
+            values.add(parsed_line.field(field));
+        }
+    }
+
+    pub fn total(&self) -> u64 {
+        self.total
+    }
+
+    pub fn top(&self) -> usize {
+        self.top
+    }
+
+    pub fn fields(&self) -> impl Iterator<Item = (&String, &TopValues)> {
+        self.fields.iter().map(|(field, values)| (field, values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::ParsedLine;
+    use crate::facets::{Facets, TopValues, ValueCount, MAX_FIELDS, MAX_TOP};
+
+    fn counts(top: &[ValueCount]) -> Vec<(&str, u64)> {
+        top.iter().map(|v| (v.value.as_str(), v.count)).collect()
+    }
+
+    #[test]
+    fn test_exact_counts() {
+        let mut values = TopValues::new(2).unwrap();
+        for v in vec!["a", "b", "a", "c", "a", "b"] {
+            values.add(Some(v));
+        }
+        values.add(None);
+        assert_eq!(counts(&values.top(2)), vec![("a", 3), ("b", 2)]);
+        assert_eq!(values.missing(), 1);
+        assert!(!values.approximate());
+    }
+
+    #[test]
+    fn test_bounded_memory() {
+        let mut values = TopValues::new(1).unwrap();
+        for i in 0..10_000 {
+            values.add(Some(&format!("rare{}", i)));
+            if i % 2 == 0 {
+                values.add(Some("frequent"));
+            }
+        }
+        assert!(values.counters.len() <= 100);
+        let linked: usize = values.by_count.values().map(|values| values.len()).sum();
+        assert_eq!(linked, values.counters.len());
+        assert!(values.approximate());
+        let top = values.top(1);
+        assert_eq!(top[0].value, "frequent");
+        assert!(top[0].count >= 5_000);
+        assert!(top[0].count - top[0].error <= 5_000);
+    }
+
+    #[test]
+    fn test_facets_of_fields() {
+        let mut facets = Facets::new(&["loglevel".to_string(), "client".to_string()], 5).unwrap();
+        for (level, client) in vec![
+            ("INFO", Some("10.0.0.1")),
+            ("ERROR", None),
+            ("INFO", Some("10.0.0.1")),
+        ] {
+            let mut line = ParsedLine {
+                timestamp: 1,
+                loglevel: Some(level.to_string()),
+                message: String::new(),
+                fields: Default::default(),
+            };
+            if let Some(client) = client {
+                line.fields.insert("client".to_string(), client.to_string());
+            }
+            facets.add(&line);
+        }
+        assert_eq!(facets.total(), 3);
+        let fields: Vec<_> = facets.fields().collect();
+        assert_eq!(counts(&fields[0].1.top(5)), vec![("INFO", 2), ("ERROR", 1)]);
+        assert_eq!(counts(&fields[1].1.top(5)), vec![("10.0.0.1", 2)]);
+        assert_eq!(fields[1].1.missing(), 1);
+    }
+
+    #[test]
+    fn test_limited_top() {
+        assert!(TopValues::new(MAX_TOP).is_ok());
+        assert!(TopValues::new(MAX_TOP + 1).is_err());
+        assert!(TopValues::new(usize::MAX).is_err());
+        assert!(Facets::new(&["loglevel".to_string()], usize::MAX).is_err());
+    }
+    #[test]
+    fn test_limited_fields() {
+        let repeated = vec!["loglevel".to_string(); MAX_FIELDS + 1];
+        let facets = Facets::new(&repeated, 5).unwrap();
+        assert_eq!(facets.fields().count(), 1);
+
+        let fields: Vec<String> = (0..MAX_FIELDS).map(|i| format!("f{}", i)).collect();
+        assert!(Facets::new(&fields, 5).is_ok());
+        let fields: Vec<String> = (0..=MAX_FIELDS).map(|i| format!("f{}", i)).collect();
+        assert!(Facets::new(&fields, 5).is_err());
+    }
+}
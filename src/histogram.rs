@@ -0,0 +1,180 @@
+use crate::data::ApplicationError;
+use crate::data::ParsedLine;
+use std::collections::BTreeMap;
+
+// key used for entries without a value for the grouped field
+const UNKNOWN_GROUP: &str = "unknown";
+
+// upper bound of the non-empty buckets, which keeps the memory of a histogram bounded
+pub const MAX_BUCKETS: usize = 10_000;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupBy {
+    Loglevel,
+}
+
+impl GroupBy {
+    pub fn parse(field: &str) -> Result<GroupBy, ApplicationError> {
+        match field {
+            "loglevel" => Ok(GroupBy::Loglevel),
+            f => Err(ApplicationError::InvalidQuery(format!(
+                "Unsupported group_by field: {}",
+                f
+            ))),
+        }
+    }
+
+    fn key<'a>(&self, parsed_line: &'a ParsedLine) -> &'a str {
+        match self {
+            GroupBy::Loglevel => parsed_line.loglevel.as_deref().unwrap_or(UNKNOWN_GROUP),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, PartialEq, Default)]
+pub struct Counts {
+    pub count: u64,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub groups: BTreeMap<String, u64>,
+}
+
+impl Counts {
+    fn add(&mut self, group: Option<&str>) {
+        self.count += 1;
+        if let Some(group) = group {
+            match self.groups.get_mut(group) {
+                Some(count) => *count += 1,
+                None => {
+                    self.groups.insert(group.to_string(), 1);
+                }
+            }
+        }
+    }
+}
+
+/// Counts entries per time bucket and optionally per value of a field.
+/// Only counters are kept, so memory depends on the number of non-empty buckets and groups,
+/// not on the number of entries. Buckets are limited to `MAX_BUCKETS`.
+/// Lines without timestamp (e.g. continuation lines of multiline messages) are not counted.
+#[derive(Debug)]
+pub struct Histogram {
+    bucket_ms: Option<u128>,
+    group_by: Option<GroupBy>,
+    total: Counts,
+    buckets: BTreeMap<u128, Counts>,
+}
+
+impl Histogram {
+    pub fn new(bucket_ms: Option<u128>, group_by: Option<GroupBy>) -> Histogram {
+        Histogram {
+            bucket_ms,
+            group_by,
+            total: Counts::default(),
+            buckets: BTreeMap::new(),
+        }
+    }
+
+    /// Count the line, fails if it would need a bucket beyond `MAX_BUCKETS`.
+    pub fn add(&mut self, parsed_line: &ParsedLine) -> Result<(), ApplicationError> {
+        if parsed_line.timestamp == 0 {
+            return Ok(());
+        }
+        let group = self.group_by.map(|g| g.key(parsed_line));
+        if let Some(bucket_ms) = self.bucket_ms {
+            let start_ms = parsed_line.timestamp - parsed_line.timestamp % bucket_ms;
+            if self.buckets.len() >= MAX_BUCKETS && !self.buckets.contains_key(&start_ms) {
+                return Err(ApplicationError::InvalidQuery(format!(
+                    "More than {} buckets, use a larger bucket size or a shorter time range",
+                    MAX_BUCKETS
+                )));
+            }
+            self.buckets.entry(start_ms).or_default().add(group);
+        }
+        self.total.add(group);
+        Ok(())
+    }
+
+    pub fn bucket_ms(&self) -> Option<u128> {
+        self.bucket_ms
+    }
+
+    pub fn total(&self) -> &Counts {
+        &self.total
+    }
+
+    /// Non-empty buckets in ascending order of their start time.
+    pub fn buckets(&self) -> impl Iterator<Item = (&u128, &Counts)> {
+        self.buckets.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::ParsedLine;
+    use crate::histogram::{GroupBy, Histogram, MAX_BUCKETS};
+
+    fn line(timestamp: u128, loglevel: Option<&str>) -> ParsedLine {
+        ParsedLine {
+            timestamp,
+            loglevel: loglevel.map(|l| l.to_string()),
+            message: String::new(),
+            fields: Default::default(),
+        }
+    }
+
+    #[test]
+    fn test_total_only() {
+        let mut histogram = Histogram::new(None, None);
+        for line in &[
+            line(1000, Some("INFO")),
+            line(0, None),
+            line(2000, Some("ERROR")),
+        ] {
+            histogram.add(line).unwrap();
+        }
+        assert_eq!(histogram.total().count, 2);
+        assert!(histogram.total().groups.is_empty());
+        assert_eq!(histogram.buckets().count(), 0);
+    }
+
+    #[test]
+    fn test_buckets_by_loglevel() {
+        let mut histogram = Histogram::new(Some(60_000), Some(GroupBy::Loglevel));
+        for line in &[
+            line(130_000, Some("INFO")),
+            line(10_000, Some("INFO")),
+            line(59_999, Some("ERROR")),
+            line(60_000, None),
+        ] {
+            histogram.add(line).unwrap();
+        }
+
+        assert_eq!(histogram.total().count, 4);
+        assert_eq!(histogram.total().groups.get("INFO"), Some(&2));
+        assert_eq!(histogram.total().groups.get("unknown"), Some(&1));
+
+        let buckets: Vec<(u128, u64)> = histogram
+            .buckets()
+            .map(|(start, counts)| (*start, counts.count))
+            .collect();
+        assert_eq!(buckets, vec![(0, 2), (60_000, 1), (120_000, 1)]);
+    }
+
+    #[test]
+    fn test_bucket_limit() {
+        let mut histogram = Histogram::new(Some(1), None);
+        for ts in 1..=MAX_BUCKETS as u128 {
+            histogram.add(&line(ts, None)).unwrap();
+        }
+        // existing buckets are still counted
+        histogram.add(&line(1, None)).unwrap();
+        assert!(histogram.add(&line(0xffff_ffff, None)).is_err());
+        assert_eq!(histogram.total().count, MAX_BUCKETS as u64 + 1);
+    }
+
+    #[test]
+    fn test_parse_group_by() {
+        assert_eq!(GroupBy::parse("loglevel").unwrap(), GroupBy::Loglevel);
+        assert!(GroupBy::parse("message").is_err());
+    }
+}
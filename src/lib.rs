@@ -7,15 +7,29 @@ use crate::constants::AUTHORS;
 use crate::constants::VERSION;
 
 mod cfg;
+mod compression;
 mod constants;
+mod cursor;
 mod data;
+mod delimited;
+mod facets;
+mod histogram;
+mod log_context;
+mod log_dedupe;
+mod log_limit;
 mod log_merge;
 mod log_source;
 mod logsource_port;
 mod logsource_svc;
+mod otlp;
+mod proto;
 mod server;
+mod sse;
 mod state;
+mod template;
+mod time_range;
 mod util;
+mod websocket;
 
 pub fn version() -> &'static str {
     VERSION
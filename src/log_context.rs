@@ -0,0 +1,117 @@
+use crate::data::{ApplicationError, LogStream, StreamEntry};
+use core::pin::Pin;
+use core::task::Context;
+use futures::stream::{Stream, StreamExt};
+use futures::task::Poll;
+use std::collections::VecDeque;
+
+/// Reduces a stream of matching and context entries (as marked by `StreamEntry::context`)
+/// to the matches with up to `before` preceding and `after` following context entries.
+/// Overlapping windows are merged, each entry is delivered at most once.
+pub struct LogContext {
+    source: LogStream,
+    before: usize,
+    after: usize,
+    buffer: VecDeque<StreamEntry>, // context entries which may precede the next match
+    pending: VecDeque<StreamEntry>, // entries ready for delivery
+    after_left: usize,
+}
+
+impl LogContext {
+    pub fn new(source: LogStream, before: usize, after: usize) -> LogContext {
+        LogContext {
+            source,
+            before,
+            after,
+            buffer: VecDeque::with_capacity(before),
+            pending: VecDeque::with_capacity(before + 1),
+            after_left: 0,
+        }
+    }
+
+    fn process(&mut self, entry: StreamEntry) {
+        if !entry.context {
+            self.pending.append(&mut self.buffer);
+            self.pending.push_back(entry);
+            self.after_left = self.after;
+        } else if self.after_left > 0 {
+            self.after_left -= 1;
+            self.pending.push_back(entry);
+        } else if self.before > 0 {
+            if self.buffer.len() == self.before {
+                self.buffer.pop_front();
+            }
+            self.buffer.push_back(entry);
+        }
+    }
+}
+
+impl Stream for LogContext {
+    type Item = Result<StreamEntry, ApplicationError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(entry) = self.pending.pop_front() {
+                return Poll::Ready(Some(Ok(entry)));
+            }
+            match self.source.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(entry))) => self.process(entry),
+                other => return other,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{LogStream, ParsedLine, StreamEntry};
+    use crate::log_context::LogContext;
+    use async_std::task;
+    use futures::stream;
+    use futures::stream::StreamExt;
+
+    fn entry(timestamp: u128, context: bool) -> StreamEntry {
+        StreamEntry {
+            line: format!("line {}", timestamp),
+            parsed_line: ParsedLine {
+                timestamp,
+                loglevel: None,
+                message: format!("line {}", timestamp),
+                fields: Default::default(),
+            },
+            cursor: None,
+            context,
+            marker: None,
+            repeat: None,
+            origin: None,
+        }
+    }
+
+    fn with_context(matches: &[u128], before: usize, after: usize) -> Vec<u128> {
+        let entries: Vec<_> = (0..20)
+            .map(|ts| Ok(entry(ts, !matches.contains(&ts))))
+            .collect();
+        let source: LogStream = stream::iter(entries).boxed_local();
+        let result = LogContext::new(source, before, after)
+            .map(|e| e.unwrap().timestamp())
+            .collect::<Vec<u128>>();
+        task::block_on(result)
+    }
+
+    #[test]
+    fn test_no_context() {
+        assert_eq!(with_context(&[3, 10], 0, 0), vec![3, 10]);
+    }
+
+    #[test]
+    fn test_before_and_after() {
+        assert_eq!(with_context(&[3, 10], 2, 1), vec![1, 2, 3, 4, 8, 9, 10, 11]);
+        assert_eq!(with_context(&[0, 19], 2, 2), vec![0, 1, 2, 17, 18, 19]);
+    }
+
+    #[test]
+    fn test_overlapping_windows() {
+        assert_eq!(with_context(&[5, 7], 2, 2), vec![3, 4, 5, 6, 7, 8, 9]);
+        assert_eq!(with_context(&[5, 6], 1, 0), vec![4, 5, 6]);
+    }
+}
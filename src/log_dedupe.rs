@@ -0,0 +1,254 @@
+use crate::data::{ApplicationError, LogStream, Repeat, StreamEntry};
+use core::pin::Pin;
+use core::task::Context;
+use futures::future::Future;
+use futures::stream::{Stream, StreamExt};
+use futures::task::Poll;
+use regex::Regex;
+use std::time::Duration;
+
+// a run of repeated entries is delivered, if no further entry arrives within this period
+const QUIET_PERIOD: Duration = Duration::from_secs(2);
+
+// numbers and hex ids, i.e. words of hex digits containing at least one decimal digit
+const MASK_PATTERN: &str = r"\b(0x)?[0-9a-fA-F]*[0-9][0-9a-fA-F]*\b";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DedupeMode {
+    Exact,  // identical messages
+    Masked, // messages identical after masking numbers and hex ids
+}
+
+impl DedupeMode {
+    pub fn parse(mode: &str) -> Result<DedupeMode, ApplicationError> {
+        match mode {
+            "exact" | "true" => Ok(DedupeMode::Exact),
+            "masked" => Ok(DedupeMode::Masked),
+            m => Err(ApplicationError::InvalidQuery(format!(
+                "Unsupported dedupe mode: {}",
+                m
+            ))),
+        }
+    }
+}
+
+struct Run {
+    key: String,
+    first: StreamEntry,
+    last: StreamEntry,
+    count: u64,
+}
+
+impl Run {
+    fn into_entry(self) -> StreamEntry {
+        if self.count == 1 {
+            self.first
+        } else {
+            StreamEntry {
+                // runs are read backwards in newest-first order
+                repeat: Some(Repeat {
+                    count: self.count,
+                    first_ts: self.first.timestamp().min(self.last.timestamp()),
+                    last_ts: self.first.timestamp().max(self.last.timestamp()),
+                }),
+                // resuming continues after the last entry of the run
+                cursor: self.last.cursor,
+                ..self.first
+            }
+        }
+    }
+}
+
+/// Collapses consecutive entries with the same loglevel and message into the first entry of the run,
+/// annotated with the number of repetitions and the timestamps of the first and last entry.
+/// Markers and errors end a run and are passed through.
+/// Runs are collapsed per source, before sources are merged: a run ends with the position of
+/// its last entry, which resumes a single source only, so repeats across sources are kept.
+pub struct LogDedupe {
+    source: LogStream,
+    mask: Option<Regex>,
+    run: Option<Run>,
+    pending: Option<Result<StreamEntry, ApplicationError>>,
+    quiet_timer: Option<Pin<Box<dyn Future<Output = ()>>>>,
+    finished: bool,
+}
+
+impl LogDedupe {
+    pub fn new(source: LogStream, mode: DedupeMode) -> LogDedupe {
+        let mask = match mode {
+            DedupeMode::Exact => None,
+            DedupeMode::Masked => Some(Regex::new(MASK_PATTERN).unwrap()),
+        };
+        LogDedupe {
+            source,
+            mask,
+            run: None,
+            pending: None,
+            quiet_timer: None,
+            finished: false,
+        }
+    }
+
+    fn key(&self, entry: &StreamEntry) -> String {
+        let message = match &self.mask {
+            Some(mask) => mask.replace_all(&entry.parsed_line.message, "#"),
+            None => entry.parsed_line.message.as_str().into(),
+        };
+        format!(
+            "{}|{}|{}",
+            entry.context,
+            entry.parsed_line.loglevel.as_deref().unwrap_or(""),
+            message
+        )
+    }
+
+    /// Adds the entry to the current run, or starts a new one and returns the finished run.
+    fn add(&mut self, entry: StreamEntry) -> Option<StreamEntry> {
+        let key = self.key(&entry);
+        if let Some(run) = &mut self.run {
+            if run.key == key {
+                run.count += 1;
+                run.last = entry;
+                return None;
+            }
+        }
+        let finished = self.run.take().map(Run::into_entry);
+        self.run = Some(Run {
+            key,
+            first: entry.clone(),
+            last: entry,
+            count: 1,
+        });
+        finished
+    }
+}
+
+impl Stream for LogDedupe {
+    type Item = Result<StreamEntry, ApplicationError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if let Some(item) = self.pending.take() {
+            return Poll::Ready(Some(item));
+        }
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        loop {
+            match self.source.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(entry))) if entry.marker.is_none() => {
+                    self.quiet_timer = None;
+                    if let Some(finished) = self.add(entry) {
+                        return Poll::Ready(Some(Ok(finished)));
+                    }
+                }
+                Poll::Ready(Some(item)) => {
+                    self.quiet_timer = None;
+                    return match self.run.take() {
+                        Some(run) => {
+                            self.pending = Some(item);
+                            Poll::Ready(Some(Ok(run.into_entry())))
+                        }
+                        None => Poll::Ready(Some(item)),
+                    };
+                }
+                Poll::Ready(None) => {
+                    self.finished = true;
+                    return Poll::Ready(self.run.take().map(|run| Ok(run.into_entry())));
+                }
+                Poll::Pending => {
+                    // while waiting for new lines, deliver the open run after a quiet period
+                    if self.run.is_some() {
+                        let timer = self
+                            .quiet_timer
+                            .get_or_insert_with(|| Box::pin(async_std::task::sleep(QUIET_PERIOD)));
+                        if timer.as_mut().poll(cx).is_ready() {
+                            self.quiet_timer = None;
+                            let run = self.run.take().unwrap();
+                            return Poll::Ready(Some(Ok(run.into_entry())));
+                        }
+                    }
+                    return Poll::Pending;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{LogStream, ParsedLine, StreamEntry};
+    use crate::log_dedupe::{DedupeMode, LogDedupe};
+    use async_std::task;
+    use futures::stream;
+    use futures::stream::StreamExt;
+
+    fn entry(timestamp: u128, message: &str) -> StreamEntry {
+        StreamEntry {
+            line: format!("{} {}", timestamp, message),
+            parsed_line: ParsedLine {
+                timestamp,
+                loglevel: Some("ERROR".to_string()),
+                message: message.to_string(),
+                fields: Default::default(),
+            },
+            cursor: None,
+            context: false,
+            marker: None,
+            repeat: None,
+            origin: None,
+        }
+    }
+
+    fn dedupe(entries: Vec<StreamEntry>, mode: DedupeMode) -> Vec<(u128, u64, u128)> {
+        let source: LogStream = stream::iter(entries.into_iter().map(Ok)).boxed_local();
+        let deduped = LogDedupe::new(source, mode).map(|e| {
+            let e = e.unwrap();
+            match e.repeat {
+                Some(r) => (r.first_ts, r.count, r.last_ts),
+                None => (e.timestamp(), 1, e.timestamp()),
+            }
+        });
+        task::block_on(deduped.collect::<Vec<_>>())
+    }
+
+    #[test]
+    fn test_exact() {
+        let entries = vec![
+            entry(1, "crash"),
+            entry(2, "crash"),
+            entry(3, "crash"),
+            entry(4, "started"),
+            entry(5, "crash"),
+            entry(6, "crash"),
+        ];
+        assert_eq!(
+            dedupe(entries, DedupeMode::Exact),
+            vec![(1, 3, 3), (4, 1, 4), (5, 2, 6)]
+        );
+    }
+
+    #[test]
+    fn test_masked() {
+        let entries = vec![
+            entry(1, "request 17 failed for id 0x3fa2"),
+            entry(2, "request 18 failed for id 0x3fa9"),
+            entry(3, "request 19 failed for id c0ffee42"),
+            entry(4, "request 19 failed for user bob"),
+        ];
+        assert_eq!(
+            dedupe(entries.clone(), DedupeMode::Masked),
+            vec![(1, 3, 3), (4, 1, 4)]
+        );
+        assert_eq!(dedupe(entries, DedupeMode::Exact).len(), 4);
+    }
+
+    #[test]
+    fn test_flush_after_quiet_period() {
+        let source: LogStream = stream::iter(vec![Ok(entry(1, "crash")), Ok(entry(2, "crash"))])
+            .chain(stream::pending())
+            .boxed_local();
+        let mut deduped = LogDedupe::new(source, DedupeMode::Exact);
+        let first = task::block_on(deduped.next()).unwrap().unwrap();
+        assert_eq!(first.repeat.unwrap().count, 2);
+    }
+}
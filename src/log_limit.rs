@@ -0,0 +1,421 @@
+use crate::data::{Marker, MarkerReason, ParsedLine, StreamEntry};
+use crate::util;
+use core::pin::Pin;
+use core::task::Context;
+use futures::future::Future;
+use futures::stream::{Stream, StreamExt};
+use futures::task::Poll;
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+const RATE_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Default, Clone)]
+pub struct Limits {
+    pub sample: Option<f64>,    // fraction of entries to deliver
+    pub max_rate: Option<u64>,  // entries per second
+    pub max_lines: Option<u64>, // entries in total
+}
+
+/// Applies sampling and rate and line limits to a stream of entries.
+/// Left out entries are reported by marker entries: skipped entries due to the rate limit
+/// before the next delivered entry, the number of entries left out by the rate limit and
+/// by sampling and the line limit at the end of the stream. While the source waits for entries,
+/// as when following, the entries left out so far are reported after the rate interval.
+/// The stream ends once `max_lines` entries are delivered.
+/// Entries are sampled by a hash of their line and position, so repeated lines are sampled independently.
+/// Context entries count against the limits, markers of the source are passed through.
+pub struct LogLimit<S> {
+    source: S,
+    limits: Limits,
+    sample_threshold: u64,
+    delivered: u64,
+    sampled_out: u64,
+    rate_window: Instant,
+    rate_count: u64,
+    rate_skipped: u64,
+    last_timestamp: u128,
+    pending: VecDeque<StreamEntry>,
+    idle_timer: Option<Pin<Box<dyn Future<Output = ()>>>>,
+    finished: bool,
+}
+
+impl<S> LogLimit<S>
+where
+    S: Stream<Item = StreamEntry> + Unpin,
+{
+    pub fn new(source: S, limits: Limits) -> LogLimit<S> {
+        let sample_threshold = limits
+            .sample
+            .map(|rate| (rate.clamp(0.0, 1.0) * u64::MAX as f64) as u64)
+            .unwrap_or(u64::MAX);
+        LogLimit {
+            source,
+            limits,
+            sample_threshold,
+            delivered: 0,
+            sampled_out: 0,
+            rate_window: Instant::now(),
+            rate_count: 0,
+            rate_skipped: 0,
+            last_timestamp: 0,
+            pending: VecDeque::new(),
+            idle_timer: None,
+            finished: false,
+        }
+    }
+
+    fn marker(&self, reason: MarkerReason, skipped: Option<u64>) -> StreamEntry {
+        let message = reason.message();
+        StreamEntry {
+            line: message.to_string(),
+            parsed_line: ParsedLine {
+                timestamp: self.last_timestamp,
+                loglevel: Some("WARN".to_string()),
+                message: message.to_string(),
+                fields: Default::default(),
+            },
+            cursor: None,
+            context: false,
+            marker: Some(Marker { reason, skipped }),
+            repeat: None,
+            origin: None,
+        }
+    }
+
+    fn sampled(&self, entry: &StreamEntry) -> bool {
+        self.limits.sample.is_none()
+            || util::stable_hash(&LogLimit::<S>::sample_key(entry)) <= self.sample_threshold
+    }
+
+    // the line with its position in the source, if known
+    fn sample_key(entry: &StreamEntry) -> Vec<u8> {
+        let mut key = entry.line.as_bytes().to_vec();
+        if let Some(cursor) = &entry.cursor {
+            key.extend(
+                format!(
+                    "\n{}:{}:{}:{}",
+                    cursor.source, cursor.dev, cursor.inode, cursor.offset
+                )
+                .bytes(),
+            );
+        } else if let Some(origin) = &entry.origin {
+            if let Some(line) = origin.line {
+                key.extend(format!("\n{}:{}:{}", origin.source, origin.file, line).bytes());
+            }
+        }
+        key
+    }
+
+    fn within_rate(&mut self) -> bool {
+        match self.limits.max_rate {
+            Some(max_rate) => {
+                let now = Instant::now();
+                if now.duration_since(self.rate_window) >= RATE_INTERVAL {
+                    self.rate_window = now;
+                    self.rate_count = 0;
+                }
+                self.rate_count += 1;
+                self.rate_count <= max_rate
+            }
+            None => true,
+        }
+    }
+
+    fn deliver(&mut self, entry: StreamEntry) -> StreamEntry {
+        if entry.marker.is_none() {
+            self.delivered += 1;
+        }
+        if entry.timestamp() > 0 {
+            self.last_timestamp = entry.timestamp();
+        }
+        entry
+    }
+
+    fn max_lines_reached(&self) -> bool {
+        matches!(self.limits.max_lines, Some(max_lines) if self.delivered >= max_lines)
+    }
+
+    /// Queue the markers of the entries left out since they were reported last.
+    fn report_left_out(&mut self) {
+        if self.rate_skipped > 0 {
+            let marker = self.marker(MarkerReason::MaxRate, Some(self.rate_skipped));
+            self.pending.push_back(marker);
+            self.rate_skipped = 0;
+        }
+        if self.sampled_out > 0 {
+            let marker = self.marker(MarkerReason::Sample, Some(self.sampled_out));
+            self.pending.push_back(marker);
+            self.sampled_out = 0;
+        }
+    }
+
+    /// End the stream with the markers of the entries left out, followed by the line limit marker
+    /// if the stream ends there.
+    fn finish(&mut self, max_lines: bool) -> Option<StreamEntry> {
+        self.finished = true;
+        self.report_left_out();
+        if max_lines {
+            let marker = self.marker(MarkerReason::MaxLines, None);
+            self.pending.push_back(marker);
+        }
+        self.pending.pop_front()
+    }
+}
+
+impl<S> Stream for LogLimit<S>
+where
+    S: Stream<Item = StreamEntry> + Unpin,
+{
+    type Item = StreamEntry;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if let Some(entry) = self.pending.pop_front() {
+            return Poll::Ready(Some(self.deliver(entry)));
+        }
+        if self.finished {
+            return Poll::Ready(None);
+        }
+        if self.max_lines_reached() {
+            // entries are left out if there are more, a source still waiting for entries may have more
+            let more = !matches!(self.source.poll_next_unpin(cx), Poll::Ready(None));
+            return Poll::Ready(self.finish(more));
+        }
+        loop {
+            match self.source.poll_next_unpin(cx) {
+                Poll::Ready(Some(entry)) => {
+                    self.idle_timer = None;
+                    if entry.marker.is_some() {
+                        return Poll::Ready(Some(self.deliver(entry)));
+                    }
+                    if !self.sampled(&entry) {
+                        self.sampled_out += 1;
+                        continue;
+                    }
+                    if !self.within_rate() {
+                        self.rate_skipped += 1;
+                        continue;
+                    }
+                    if self.rate_skipped > 0 {
+                        let marker = self.marker(MarkerReason::MaxRate, Some(self.rate_skipped));
+                        self.rate_skipped = 0;
+                        self.pending.push_back(entry);
+                        return Poll::Ready(Some(marker));
+                    }
+                    return Poll::Ready(Some(self.deliver(entry)));
+                }
+                Poll::Ready(None) => return Poll::Ready(self.finish(false)),
+                Poll::Pending => {
+                    if self.rate_skipped == 0 && self.sampled_out == 0 {
+                        return Poll::Pending;
+                    }
+                    let timer = self
+                        .idle_timer
+                        .get_or_insert_with(|| Box::pin(async_std::task::sleep(RATE_INTERVAL)));
+                    if timer.as_mut().poll(cx).is_pending() {
+                        return Poll::Pending;
+                    }
+                    self.idle_timer = None;
+                    self.report_left_out();
+                    let marker = self.pending.pop_front();
+                    return Poll::Ready(marker.map(|marker| self.deliver(marker)));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cursor::SourceCursor;
+    use crate::data::Marker;
+    use crate::data::{MarkerReason, ParsedLine, StreamEntry};
+    use crate::log_limit::{Limits, LogLimit};
+    use async_std::future::timeout;
+    use async_std::task;
+    use futures::stream;
+    use futures::stream::StreamExt;
+    use std::time::Duration;
+
+    fn entries(n: u128) -> Vec<StreamEntry> {
+        (1..=n)
+            .map(|ts| StreamEntry {
+                line: format!("line {}", ts),
+                parsed_line: ParsedLine {
+                    timestamp: ts,
+                    loglevel: None,
+                    message: format!("line {}", ts),
+                    fields: Default::default(),
+                },
+                cursor: None,
+                context: false,
+                marker: None,
+                repeat: None,
+                origin: None,
+            })
+            .collect()
+    }
+
+    fn limit(n: u128, limits: Limits) -> Vec<StreamEntry> {
+        let limited = LogLimit::new(stream::iter(entries(n)), limits);
+        task::block_on(limited.collect::<Vec<StreamEntry>>())
+    }
+
+    /// Limit entries of a followed source, which waits for more entries after the given ones.
+    fn limit_following(n: u128, limits: Limits, take: usize) -> Vec<StreamEntry> {
+        let source = stream::iter(entries(n)).chain(stream::pending());
+        let limited = LogLimit::new(source, limits).take(take);
+        task::block_on(timeout(Duration::from_secs(3), limited.collect())).unwrap()
+    }
+
+    fn reason(entry: &StreamEntry) -> Option<MarkerReason> {
+        entry.marker.as_ref().map(|m| m.reason)
+    }
+
+    #[test]
+    fn test_no_limits() {
+        assert_eq!(limit(10, Limits::default()), entries(10));
+    }
+
+    #[test]
+    fn test_max_lines() {
+        let limits = Limits {
+            max_lines: Some(3),
+            ..Limits::default()
+        };
+        let result = limit(10, limits.clone());
+        assert_eq!(result.len(), 4);
+        assert_eq!(result[..3].to_vec(), entries(3));
+        assert_eq!(reason(&result[3]), Some(MarkerReason::MaxLines));
+        assert_eq!(result[3].timestamp(), 3);
+
+        // no marker if nothing was left out
+        assert_eq!(limit(3, limits), entries(3));
+    }
+
+    #[test]
+    fn test_sample() {
+        let limits = Limits {
+            sample: Some(0.1),
+            ..Limits::default()
+        };
+        let result = limit(1000, limits.clone());
+        let delivered = result.len() as u64 - 1;
+        assert!(delivered > 50 && delivered < 150);
+        let marker = result.last().unwrap().marker.as_ref().unwrap();
+        assert_eq!(marker.reason, MarkerReason::Sample);
+        assert_eq!(marker.skipped, Some(1000 - delivered));
+        // deterministic
+        assert_eq!(limit(1000, limits), result);
+    }
+
+    #[test]
+    fn test_sample_repeated_lines() {
+        let limits = Limits {
+            sample: Some(0.5),
+            ..Limits::default()
+        };
+        let repeated: Vec<StreamEntry> = entries(1000)
+            .into_iter()
+            .enumerate()
+            .map(|(idx, entry)| StreamEntry {
+                line: "same line".to_string(),
+                cursor: Some(SourceCursor {
+                    source: "demo".to_string(),
+                    dev: 1,
+                    inode: 2,
+                    offset: idx as u64 * 10,
+                    line: None,
+                    desc: false,
+                }),
+                ..entry
+            })
+            .collect();
+        let limited = LogLimit::new(stream::iter(repeated), limits);
+        let result = task::block_on(limited.collect::<Vec<StreamEntry>>());
+        let delivered = result.len() as u64 - 1;
+        assert!(delivered > 400 && delivered < 600);
+    }
+
+    #[test]
+    fn test_max_lines_after_sampling() {
+        let limits = Limits {
+            sample: Some(0.5),
+            max_lines: Some(10),
+            ..Limits::default()
+        };
+        let result = limit(1000, limits);
+        let reasons: Vec<_> = result[10..].iter().map(reason).collect();
+        assert_eq!(
+            reasons,
+            vec![Some(MarkerReason::Sample), Some(MarkerReason::MaxLines)]
+        );
+    }
+
+    #[test]
+    fn test_max_rate() {
+        let limits = Limits {
+            max_rate: Some(5),
+            ..Limits::default()
+        };
+        let result = limit(100, limits);
+        assert_eq!(result.len(), 6);
+        assert_eq!(result[..5].to_vec(), entries(5));
+        let marker = result[5].marker.as_ref().unwrap();
+        assert_eq!(marker.reason, MarkerReason::MaxRate);
+        assert_eq!(marker.skipped, Some(95));
+    }
+    #[test]
+    fn test_markers_not_counted() {
+        let limits = Limits {
+            max_lines: Some(3),
+            ..Limits::default()
+        };
+        let mut source = entries(4);
+        source[1].marker = Some(Marker {
+            reason: MarkerReason::Dropped,
+            skipped: Some(10),
+        });
+        let limited = LogLimit::new(stream::iter(source), limits);
+        let result = task::block_on(limited.collect::<Vec<StreamEntry>>());
+        let lines = result.iter().filter(|entry| entry.marker.is_none()).count();
+        assert_eq!(lines, 3);
+        assert_eq!(result.len(), 4);
+    }
+
+    #[test]
+    fn test_max_lines_following() {
+        let limits = Limits {
+            max_lines: Some(3),
+            ..Limits::default()
+        };
+        // ends without waiting for further entries
+        let result = limit_following(3, limits, usize::MAX);
+        assert_eq!(result[..3].to_vec(), entries(3));
+        let reasons: Vec<_> = result[3..].iter().map(reason).collect();
+        assert_eq!(reasons, vec![Some(MarkerReason::MaxLines)]);
+    }
+
+    #[test]
+    fn test_left_out_following() {
+        let limits = Limits {
+            max_rate: Some(5),
+            ..Limits::default()
+        };
+        let result = limit_following(100, limits, 6);
+        assert_eq!(result[..5].to_vec(), entries(5));
+        let marker = result[5].marker.as_ref().unwrap();
+        assert_eq!(marker.reason, MarkerReason::MaxRate);
+        assert_eq!(marker.skipped, Some(95));
+
+        let limits = Limits {
+            sample: Some(0.1),
+            ..Limits::default()
+        };
+        let delivered = limit(1000, limits.clone()).len() - 1;
+        let result = limit_following(1000, limits, delivered + 1);
+        let marker = result[delivered].marker.as_ref().unwrap();
+        assert_eq!(marker.reason, MarkerReason::Sample);
+        assert_eq!(marker.skipped, Some(1000 - delivered as u64));
+    }
+}
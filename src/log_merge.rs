@@ -1,9 +1,12 @@
-use crate::data::{ApplicationError, LogStream, ParsedLine, StreamEntry};
+use crate::data::{
+    ApplicationError, LogStream, Marker, MarkerReason, Order, ParsedLine, StreamEntry,
+};
 use core::pin::Pin;
 use core::task::Context;
 use futures::stream::{Stream, StreamExt};
 use futures::task::Poll;
 use log::*;
+use std::collections::BTreeMap;
 use std::vec::Vec;
 
 #[derive(PartialEq)]
@@ -26,6 +29,8 @@ pub struct LogMerge {
     source_state: Vec<SourceState>,
     buffer: Vec<BufferEntry>,
     current_timestamp: u128,
+    order: Order,
+    following: bool,
 }
 
 impl LogMerge {
@@ -41,6 +46,36 @@ impl LogMerge {
             source_state: source_state,
             buffer: Vec::with_capacity(num_sources),
             current_timestamp: 0,
+            order: Order::Asc,
+            following: false,
+        }
+    }
+
+    /// Merge followed sources, where a pending source has caught up with its file and does not hold back
+    /// the entries of the others. Lines it reads later are newer than the ones delivered meanwhile.
+    pub fn with_following(mut self) -> LogMerge {
+        self.following = true;
+        self
+    }
+
+    /// Add a source to merge, as for a file created while following.
+    pub fn add(&mut self, source: LogStream) {
+        self.sources.push(source);
+        self.source_state.push(SourceState::NeedsPoll);
+        self.running_sources += 1;
+    }
+
+    /// Merge sources delivering entries in the given order, i.e. newest first for `Order::Desc`.
+    pub fn with_order(mut self, order: Order) -> LogMerge {
+        self.order = order;
+        self
+    }
+
+    fn precedes(&self, entry: &StreamEntry, other: &StreamEntry) -> bool {
+        match self.order {
+            Order::Asc => entry.timestamp() < other.timestamp(),
+            // lines without timestamp are delivered as soon as possible in both orders
+            Order::Desc => entry.timestamp() == 0 || entry.timestamp() > other.timestamp(),
         }
     }
 
@@ -58,7 +93,7 @@ impl LogMerge {
         let buffer_size = self.buffer.len();
         let mut insert_at = 0;
         for idx in 0..buffer_size {
-            if line.log_line.timestamp() < self.buffer[idx].log_line.timestamp() {
+            if self.precedes(&line.log_line, &self.buffer[idx].log_line) {
                 break;
             }
             insert_at += 1;
@@ -66,15 +101,26 @@ impl LogMerge {
         self.buffer.insert(insert_at, line);
     }
 
+    /// Note the failure of a source by a marker entry, the remaining entries of the source are missing.
     fn inject_error(&mut self, _err: ApplicationError, source_idx: usize) {
+        let reason = MarkerReason::Failed;
         let error = ParsedLine {
             timestamp: self.current_timestamp,
             loglevel: Some(format!("ERROR")),
-            message: format!("A tentacle failed while retrieving the log."),
+            message: reason.message().to_string(),
+            fields: BTreeMap::new(),
         };
         let log_line = StreamEntry {
             line: String::from("Failed while retrieving the log."),
             parsed_line: error,
+            cursor: None,
+            context: false,
+            marker: Some(Marker {
+                reason,
+                skipped: None,
+            }),
+            repeat: None,
+            origin: None,
         };
         self.insert_into_buffer(log_line, source_idx);
     }
@@ -84,6 +130,7 @@ impl Stream for LogMerge {
     type Item = StreamEntry;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut pending = 0;
         for s in 0..(*self).source_state.len() {
             match (*self).source_state[s] {
                 SourceState::NeedsPoll => match (*self).sources[s].poll_next_unpin(cx) {
@@ -97,6 +144,7 @@ impl Stream for LogMerge {
                     }
                     Poll::Pending => {
                         (*self).source_state[s] = SourceState::NeedsPoll;
+                        pending += 1;
                     }
                     Poll::Ready(Some(Err(e))) => {
                         error!("Source failed: {}", e);
@@ -108,9 +156,13 @@ impl Stream for LogMerge {
                 _ => {}
             }
         }
+        // pending followed sources wait for lines newer than the buffered ones
+        let caught_up = self.following
+            && !self.buffer.is_empty()
+            && self.running_sources - pending <= self.buffer.len();
         if (*self).running_sources == 0 && (*self).buffer.is_empty() {
             Poll::Ready(None)
-        } else if (*self).running_sources <= (*self).buffer.len() {
+        } else if (*self).running_sources <= (*self).buffer.len() || caught_up {
             let entry = (*self).next_entry();
             if (*self).source_state[entry.source_idx] == SourceState::Delivered {
                 (*self).source_state[entry.source_idx] = SourceState::NeedsPoll;
@@ -125,7 +177,7 @@ impl Stream for LogMerge {
 
 #[cfg(test)]
 mod tests {
-    use crate::data::{LogStream, ParsedLine, StreamEntry};
+    use crate::data::{ApplicationError, LogStream, MarkerReason, Order, ParsedLine, StreamEntry};
     use crate::log_merge::LogMerge;
     use async_std::task;
     use futures::stream;
@@ -136,10 +188,16 @@ mod tests {
             timestamp: timestamp,
             message: line.to_string(),
             loglevel: None,
+            fields: Default::default(),
         };
         StreamEntry {
             line: String::from("Failed while retrieving the log."),
             parsed_line: error,
+            cursor: None,
+            context: false,
+            marker: None,
+            repeat: None,
+            origin: None,
         }
     }
 
@@ -200,4 +258,57 @@ mod tests {
         let result = task::block_on(merge.collect::<Vec<StreamEntry>>());
         assert_eq!(vec![l21, l11, l31, l32, l12, l33, l22, l13, l34], result);
     }
+
+    #[test]
+    fn test_descending() {
+        let l11 = line_at(520, "s11");
+        let l12 = line_at(100, "s12");
+        let l21 = line_at(430, "s21");
+        let l22 = line_at(0, "s22");
+        let l23 = line_at(90, "s23");
+        let s1: LogStream = stream::iter(vec![Ok(l11.clone()), Ok(l12.clone())]).boxed_local();
+        let s2: LogStream =
+            stream::iter(vec![Ok(l21.clone()), Ok(l22.clone()), Ok(l23.clone())]).boxed_local();
+        let merge = LogMerge::new(vec![s1, s2]).with_order(Order::Desc);
+        let result = task::block_on(merge.collect::<Vec<StreamEntry>>());
+        assert_eq!(vec![l11, l21, l22, l12, l23], result);
+    }
+
+    #[test]
+    fn test_following() {
+        let l11 = line_at(100, "s11");
+        let l12 = line_at(300, "s12");
+        let l21 = line_at(200, "s21");
+        let l31 = line_at(150, "s31");
+        // the first source waits for more lines after its second one
+        let s1: LogStream = stream::iter(vec![Ok(l11.clone()), Ok(l12.clone())])
+            .chain(stream::pending())
+            .boxed_local();
+        let s2: LogStream = stream::iter(vec![Ok(l21.clone())]).boxed_local();
+        let mut merge = LogMerge::new(vec![s1, s2]).with_following();
+        let result = task::block_on(merge.by_ref().take(3).collect::<Vec<StreamEntry>>());
+        assert_eq!(vec![l11, l21, l12], result);
+
+        merge.add(stream::iter(vec![Ok(l31.clone())]).boxed_local());
+        let result = task::block_on(merge.take(1).collect::<Vec<StreamEntry>>());
+        assert_eq!(vec![l31], result);
+    }
+    #[test]
+    fn test_failing_source() {
+        let l11 = line_at(100, "s11");
+        let l12 = line_at(300, "s12");
+        let l21 = line_at(200, "s21");
+        let s1: LogStream = stream::iter(vec![Ok(l11.clone()), Ok(l12.clone())]).boxed_local();
+        let s2: LogStream = stream::iter(vec![
+            Ok(l21.clone()),
+            Err(ApplicationError::FailedToReadSource),
+        ])
+        .boxed_local();
+        let merge = LogMerge::new(vec![s1, s2]);
+        let result = task::block_on(merge.collect::<Vec<StreamEntry>>());
+        assert_eq!(result.len(), 4);
+        let failed = result.iter().find(|entry| entry.marker.is_some()).unwrap();
+        assert_eq!(failed.marker.as_ref().unwrap().reason, MarkerReason::Failed);
+        assert_eq!(failed.parsed_line.timestamp, 200);
+    }
 }
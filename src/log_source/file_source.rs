@@ -1,69 +1,607 @@
+use crate::cursor::SourceCursor;
 use crate::data::ApplicationError;
 use crate::data::LinePattern;
 use crate::data::LogQueryContext;
 use crate::data::LogStream;
+use crate::data::Order;
+use crate::data::Origin;
 use crate::data::ParsedLine;
 use crate::data::StreamEntry;
+use crate::log_merge::LogMerge;
+use crate::log_source::file_tail::TailService;
+use crate::log_source::file_watch::FileWatcher;
+use crate::log_source::file_watch::Watch;
 use crate::util;
 use chrono::Datelike;
 use chrono::TimeZone;
 use core::pin::Pin;
 use flate2::read::GzDecoder;
+use futures::future::Future;
 use futures::stream::Stream;
 use futures::task::Context;
 use futures::task::Poll;
 use futures_util::stream::StreamExt;
 use regex::Regex;
+use std::cell::RefCell;
 use std::cmp::Ordering;
+use std::collections::BTreeMap;
+use std::collections::HashMap;
+use std::collections::HashSet;
 use std::fs;
 use std::fs::read_dir;
 use std::fs::DirEntry;
 use std::io::BufRead;
 use std::io::BufReader;
-use std::io::Lines;
+use std::io::Read;
+use std::io::Seek;
+use std::io::SeekFrom;
+use std::io::Write;
 use std::path::Path;
+use std::rc::Rc;
+use std::sync::atomic::{AtomicUsize, Ordering as AtomicOrdering};
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, Instant, SystemTime};
 
-enum LinesIter {
-    GZIP(Lines<BufReader<GzDecoder<std::fs::File>>>),
-    PLAIN(Lines<BufReader<std::fs::File>>),
+// captures of the line pattern which are not stored as additional fields
+const PREDEFINED_FIELDS: [&str; 3] = ["timestamp", "loglevel", "message"];
+
+// below this range the remaining lines are scanned instead of bisected further
+const BISECT_MIN_RANGE: u64 = 64 * 1024;
+
+// size of the blocks read at once, when reading a plain file backwards
+const REVERSE_BLOCK_SIZE: u64 = 64 * 1024;
+
+// a rotated file is read further until no lines were appended to it for this long
+const ROTATED_GRACE_PERIOD: Duration = Duration::from_secs(2);
+
+// interval of checking a rotated file for appended lines, where changes of it are not notified
+const ROTATED_CHECK_INTERVAL: Duration = Duration::from_millis(250);
+
+fn strip_line_end(buf: &mut Vec<u8>) {
+    if buf.ends_with(b"\n") {
+        buf.pop();
+        if buf.ends_with(b"\r") {
+            buf.pop();
+        }
+    }
+}
+
+enum FileReader {
+    GZIP(BufReader<GzDecoder<std::fs::File>>),
+    PLAIN(BufReader<std::fs::File>),
+}
+
+/// Line iterator which keeps track of the byte offset and number of the lines read.
+struct LinesIter {
+    reader: FileReader,
+    offset: u64,
+    line: Option<u64>, // number of the line read last within the file, if known
+    follow: bool,      // a last line without line end is still being written, for plain files only
+}
+
+impl LinesIter {
+    fn new(reader: FileReader) -> Self {
+        LinesIter {
+            reader,
+            offset: 0,
+            line: Some(0),
+            follow: false,
+        }
+    }
+
+    /// Continue reading at the given position, which must be the start of a line,
+    /// following the line with the given number, if known.
+    /// Plain files are seeked directly, gzip files are decompressed up to the position.
+    fn skip_to(&mut self, offset: u64, line: Option<u64>) -> std::io::Result<()> {
+        match &mut self.reader {
+            FileReader::PLAIN(reader) => {
+                if offset > reader.get_ref().metadata()?.len() {
+                    // file was truncated since, so the position is gone
+                    return Ok(());
+                }
+                reader.seek(SeekFrom::Start(offset))?;
+                self.offset = offset;
+                self.line = if offset == 0 { Some(0) } else { line };
+            }
+            FileReader::GZIP(_) => {
+                while self.offset < offset {
+                    match self.next() {
+                        Some(Ok(_)) => {}
+                        Some(Err(e)) => return Err(e),
+                        None => break,
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Position a plain file after its last complete line, a last line without line end is still being written.
+    fn seek_end(&mut self) -> std::io::Result<()> {
+        if let FileReader::PLAIN(reader) = &mut self.reader {
+            let mut end = reader.get_ref().metadata()?.len();
+            let mut block = Vec::new();
+            while end > 0 {
+                let start = end.saturating_sub(REVERSE_BLOCK_SIZE);
+                block.resize((end - start) as usize, 0);
+                reader.seek(SeekFrom::Start(start))?;
+                reader.read_exact(&mut block)?;
+                if let Some(idx) = block.iter().rposition(|b| *b == b'\n') {
+                    end = start + idx as u64 + 1;
+                    break;
+                }
+                end = start;
+            }
+            reader.seek(SeekFrom::Start(end))?;
+            self.offset = end;
+            self.line = if end == 0 { Some(0) } else { None };
+        }
+        Ok(())
+    }
+
+    /// Position a plain file at a line start before which all lines are older than `from_ms`,
+    /// found by bisecting the byte range of the file.
+    /// Bisecting assumes the timestamps of the lines not to decrease. Files, where the probed
+    /// timestamps contradict that, are read from the start, as are gzip files which cannot be seeked.
+    /// An out-of-order block that no probe runs into can still make lines be skipped.
+    fn seek_timestamp<F>(&mut self, from_ms: u128, timestamp: F) -> std::io::Result<()>
+    where
+        F: Fn(&str) -> u128,
+    {
+        if let FileReader::PLAIN(reader) = &mut self.reader {
+            let offset = Self::bisect(reader, from_ms, &timestamp)?.unwrap_or(0);
+            reader.seek(SeekFrom::Start(offset))?;
+            self.offset = offset;
+            self.line = if offset == 0 { Some(0) } else { None };
+        }
+        Ok(())
+    }
+
+    /// Returns the offset to start reading at or None, if the probed timestamps are not monotonic.
+    /// Lines without timestamp (parse failures, multiline messages) are skipped while probing.
+    /// The first line of the file is probed as well, so that a block of older lines hit by a probe
+    /// is not taken for the lines before `from_ms`.
+    fn bisect<F>(
+        reader: &mut BufReader<std::fs::File>,
+        from_ms: u128,
+        timestamp: &F,
+    ) -> std::io::Result<Option<u64>>
+    where
+        F: Fn(&str) -> u128,
+    {
+        let mut lo = 0;
+        let mut hi = reader.get_ref().metadata()?.len();
+        let mut hi_ts = u128::MAX;
+        if hi <= BISECT_MIN_RANGE {
+            return Ok(Some(0));
+        }
+        reader.seek(SeekFrom::Start(0))?;
+        let mut lo_ts = match Self::probe(reader, 0, hi, timestamp)? {
+            Some((_, ts)) if ts >= from_ms => return Ok(Some(0)),
+            Some((_, ts)) => ts,
+            None => return Ok(Some(0)),
+        };
+
+        let mut buf = Vec::new();
+        while hi - lo > BISECT_MIN_RANGE {
+            let mid = lo + (hi - lo) / 2;
+            reader.seek(SeekFrom::Start(mid))?;
+            // resync to the start of the next line
+            let pos = mid + reader.read_until(b'\n', &mut buf)? as u64;
+            buf.clear();
+
+            match Self::probe(reader, pos, hi, timestamp)? {
+                Some((_, ts)) if ts < lo_ts || ts > hi_ts => return Ok(None),
+                Some((line_start, ts)) if ts < from_ms => {
+                    lo = line_start;
+                    lo_ts = ts;
+                }
+                Some((_, ts)) => {
+                    hi = mid;
+                    hi_ts = ts;
+                }
+                None => hi = mid,
+            }
+        }
+        if lo > 0 && !Self::ordered_before(reader, lo, from_ms, timestamp)? {
+            return Ok(None);
+        }
+        Ok(Some(lo))
+    }
+
+    /// Returns the start and timestamp of the first line with a timestamp, reading from the line
+    /// start at `pos` up to `end`.
+    fn probe<F>(
+        reader: &mut BufReader<std::fs::File>,
+        mut pos: u64,
+        end: u64,
+        timestamp: &F,
+    ) -> std::io::Result<Option<(u64, u128)>>
+    where
+        F: Fn(&str) -> u128,
+    {
+        let mut buf = Vec::new();
+        while pos < end {
+            buf.clear();
+            let n = reader.read_until(b'\n', &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            strip_line_end(&mut buf);
+            let ts = timestamp(&String::from_utf8_lossy(&buf));
+            if ts > 0 {
+                return Ok(Some((pos, ts)));
+            }
+            pos += n as u64;
+        }
+        Ok(None)
+    }
+
+    /// Checks, that the lines within the bisect range before the landing point at `end` are in order
+    /// and all older than `from_ms`, as the probes alone do not prove that no later line is skipped.
+    fn ordered_before<F>(
+        reader: &mut BufReader<std::fs::File>,
+        end: u64,
+        from_ms: u128,
+        timestamp: &F,
+    ) -> std::io::Result<bool>
+    where
+        F: Fn(&str) -> u128,
+    {
+        let start = end.saturating_sub(BISECT_MIN_RANGE);
+        reader.seek(SeekFrom::Start(start))?;
+        let mut buf = Vec::new();
+        let mut pos = start;
+        if start > 0 {
+            // resync to the start of the next line
+            pos += reader.read_until(b'\n', &mut buf)? as u64;
+        }
+        let mut last_ts = 0;
+        // the line at the landing point is included
+        while pos <= end {
+            buf.clear();
+            let n = reader.read_until(b'\n', &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            strip_line_end(&mut buf);
+            let ts = timestamp(&String::from_utf8_lossy(&buf));
+            if ts > 0 {
+                if ts < last_ts || ts >= from_ms {
+                    return Ok(false);
+                }
+                last_ts = ts;
+            }
+            pos += n as u64;
+        }
+        Ok(true)
+    }
 }
 
 impl Iterator for LinesIter {
     type Item = std::io::Result<String>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        let mut buf = Vec::new();
+        let read = match &mut self.reader {
+            FileReader::GZIP(reader) => reader.read_until(b'\n', &mut buf),
+            FileReader::PLAIN(reader) => reader.read_until(b'\n', &mut buf),
+        };
+        match read {
+            Ok(0) => None,
+            Ok(_) if self.follow && !buf.ends_with(b"\n") => {
+                // read the line again once it is complete
+                if let FileReader::PLAIN(reader) = &mut self.reader {
+                    if let Err(e) = reader.seek(SeekFrom::Start(self.offset)) {
+                        return Some(Err(e));
+                    }
+                }
+                None
+            }
+            Ok(n) => {
+                self.offset += n as u64;
+                self.line = self.line.map(|line| line + 1);
+                strip_line_end(&mut buf);
+                Some(
+                    String::from_utf8(buf)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+                )
+            }
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Decompress a gzip file into an unnamed temporary file, which can be read backwards like a plain file.
+/// Returns the file with the number of its lines.
+fn spill_gzip<R: Read>(mut reader: R) -> std::io::Result<(std::fs::File, u64)> {
+    static SPILLED: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "tentacle-{}-{}.spill",
+        std::process::id(),
+        SPILLED.fetch_add(1, AtomicOrdering::Relaxed)
+    ));
+    let mut file = fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(&path)?;
+    // the file stays readable until it is closed
+    if let Err(e) = fs::remove_file(&path) {
+        warn!("Failed to remove {}: {:?}", path.display(), e);
+    }
+    let mut buf = vec![0; REVERSE_BLOCK_SIZE as usize];
+    let mut lines = 0;
+    let mut last = b'\n';
+    loop {
+        let n = match reader.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        };
+        lines += buf[..n].iter().filter(|b| **b == b'\n').count() as u64;
+        last = buf[n - 1];
+        file.write_all(&buf[..n])?;
+    }
+    if last != b'\n' {
+        lines += 1;
+    }
+    Ok((file, lines))
+}
+
+/// Line iterator reading a file from the end to the start, for newest-first output.
+/// Plain files are read backwards block by block, gzip files cannot be read backwards
+/// and are decompressed into a temporary file first, so memory stays bounded by the block size.
+/// The offset is the start of the line read last, so reading continues before it.
+struct ReverseLinesIter {
+    file: std::fs::File,
+    block: Vec<u8>, // bytes of the file from `start` up to the end of the next line to return
+    start: u64,
+    offset: u64,
+    line: Option<u64>, // number of the line read last within the file, if known
+}
+
+impl ReverseLinesIter {
+    fn new(reader: FileReader) -> std::io::Result<Self> {
+        let (file, line) = match reader {
+            FileReader::PLAIN(reader) => (reader.into_inner(), None),
+            FileReader::GZIP(reader) => {
+                let (file, lines) = spill_gzip(reader)?;
+                // the line after the last one
+                (file, Some(lines + 1))
+            }
+        };
+        let len = file.metadata()?.len();
+        Ok(ReverseLinesIter {
+            file,
+            block: Vec::new(),
+            start: len,
+            offset: len,
+            line,
+        })
+    }
+
+    /// Continue reading with the line before the given offset, which must be the start of a line
+    /// with the given number, if known.
+    fn skip_to(&mut self, offset: u64, line: Option<u64>) {
+        if offset > self.start + self.block.len() as u64 {
+            // file was truncated since, so the position is gone
+            return;
+        }
+        self.block.clear();
+        self.start = offset;
+        self.offset = offset;
+        self.line = line;
+    }
+
+    fn next_line(&mut self) -> std::io::Result<Option<(u64, Vec<u8>)>> {
+        loop {
+            // the last byte is the line end of the line to return
+            let search_end = self.block.len().saturating_sub(1);
+            if let Some(idx) = self.block[..search_end].iter().rposition(|b| *b == b'\n') {
+                let line = self.block.split_off(idx + 1);
+                return Ok(Some((self.start + idx as u64 + 1, line)));
+            }
+            if self.start == 0 {
+                if self.block.is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some((0, std::mem::take(&mut self.block))));
+            }
+            let read_start = self.start.saturating_sub(REVERSE_BLOCK_SIZE);
+            let mut buf = vec![0; (self.start - read_start) as usize];
+            self.file.seek(SeekFrom::Start(read_start))?;
+            self.file.read_exact(&mut buf)?;
+            buf.append(&mut self.block);
+            self.block = buf;
+            self.start = read_start;
+        }
+    }
+}
+
+impl Iterator for ReverseLinesIter {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_line() {
+            Ok(Some((offset, mut buf))) => {
+                self.offset = offset;
+                self.line = self.line.map(|line| line.saturating_sub(1));
+                strip_line_end(&mut buf);
+                Some(
+                    String::from_utf8(buf)
+                        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+                )
+            }
+            Ok(None) => None,
+            Err(e) => Some(Err(e)),
+        }
+    }
+}
+
+/// Lines of a file in the requested order, with the position of the line read last.
+enum Lines {
+    Forward(Box<LinesIter>),
+    Backward(ReverseLinesIter),
+}
+
+impl Lines {
+    fn last_position(&self) -> u64 {
         match self {
-            LinesIter::GZIP(it) => it.next(),
-            LinesIter::PLAIN(it) => it.next(),
+            Lines::Forward(iter) => iter.offset,
+            Lines::Backward(iter) => iter.offset,
         }
     }
+
+    /// Number of the line read last within the file, if known.
+    /// It is not known for lines of plain files read after seeking by timestamp or backwards from the end.
+    fn line_number(&self) -> Option<u64> {
+        match self {
+            Lines::Forward(iter) => iter.line,
+            Lines::Backward(iter) => iter.line,
+        }
+    }
+}
+
+impl Iterator for Lines {
+    type Item = std::io::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self {
+            Lines::Forward(iter) => iter.next(),
+            Lines::Backward(iter) => iter.next(),
+        }
+    }
+}
+
+/// Position after the lines read from a file, by the identity of the file and the byte offset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(super) struct FilePosition {
+    identity: (u64, u64),
+    offset: u64,
+}
+
+/// A followed file after it was rotated. Its writer may append to it until reopening its log,
+/// so it is read along with the new file at the path.
+struct RotatedFile {
+    lines_iter: Lines,
+    identity: (u64, u64),
+    watch: Option<Watch>, // notifies about appended lines, as far as the file itself is watched
+    quiet_since: Instant,
+    timer: Option<Pin<Box<dyn Future<Output = ()> + Send>>>,
 }
 
-struct FileLogStream {
+pub(super) struct FileLogStream {
     path: String,
+    source_id: String,
+    origin: Origin,
     line_pattern: Arc<LinePattern>,
     context: Arc<LogQueryContext>,
-    lines_iter: Option<LinesIter>,
+    lines_iter: Option<Lines>,
+    identity: (u64, u64),
+    resume_at: Option<SourceCursor>,
     year: i32,
-    watch: bool,
+    watch: Option<Watch>,
+    rotated: Option<RotatedFile>,
+    tail: bool,                  // read only lines written after opening
+    until: Option<FilePosition>, // lines of the file after this position are read by others
 }
 
 impl FileLogStream {
-    fn new(path: &str, line_pattern: &Arc<LinePattern>, context: &Arc<LogQueryContext>) -> Self {
+    pub(super) fn new(
+        path: &str,
+        source_id: &str,
+        line_pattern: &Arc<LinePattern>,
+        context: &Arc<LogQueryContext>,
+    ) -> Self {
         FileLogStream {
             path: path.to_owned(),
+            source_id: source_id.to_owned(),
+            origin: Origin {
+                source: Arc::new(source_id.to_owned()),
+                file: Arc::new(path.to_owned()),
+                line: None,
+            },
             line_pattern: line_pattern.clone(),
             context: context.clone(),
             lines_iter: None,
+            identity: (0, 0),
+            resume_at: None,
             year: 0,
-            watch: false,
+            watch: None,
+            rotated: None,
+            tail: false,
+            until: None,
         }
     }
 
-    fn with_watch(mut self) -> Self {
-        self.watch = true;
+    /// Follow the file once its end is reached.
+    pub(super) fn with_watch(mut self, watcher: &FileWatcher) -> Self {
+        self.watch = Some(watcher.watch(&self.path));
+        self
+    }
+
+    /// Start reading at the end of the file.
+    pub(super) fn with_tail(mut self) -> Self {
+        self.tail = true;
+        self
+    }
+
+    /// Read the lines up to the shared tail of the file, then follow the tail.
+    /// If the tail reads another file than the one opened, as the file was rotated meanwhile,
+    /// only the tail is followed.
+    fn with_shared_tail(mut self, tails: &TailService) -> LogStream {
+        // opened before subscribing, so both start from the same file unless it is rotated meanwhile
+        if let Err(e) = self.open() {
+            error!("Failed to open {}: {:?}", self.path, e);
+            return futures::stream::empty().boxed_local();
+        }
+        match tails.subscribe(
+            &self.source_id,
+            &self.path,
+            &self.line_pattern,
+            &self.context,
+        ) {
+            Ok((subscription, position)) if position.identity == self.identity => {
+                self.with_until(position).chain(subscription).boxed_local()
+            }
+            Ok((subscription, _)) => {
+                // the tail has not switched to the file opened here yet, so it delivers all its lines
+                debug!(
+                    "{} was rotated while subscribing, following the tail only",
+                    self.path
+                );
+                subscription.boxed_local()
+            }
+            Err(e) => {
+                error!("Failed to follow {}: {:?}", self.path, e);
+                self.boxed_local()
+            }
+        }
+    }
+
+    /// Stop reading at the given position, if it is one in this file.
+    fn with_until(mut self, position: FilePosition) -> Self {
+        self.until = Some(position);
+        self
+    }
+
+    /// Position after the lines read, once opened.
+    pub(super) fn position(&self) -> FilePosition {
+        let offset = match &self.lines_iter {
+            Some(lines_iter) => lines_iter.last_position(),
+            None => 0,
+        };
+        FilePosition {
+            identity: self.identity,
+            offset,
+        }
+    }
+
+    /// Start reading after the line the cursor points to.
+    fn with_resume(mut self, cursor: SourceCursor) -> Self {
+        self.resume_at = Some(cursor);
         self
     }
 
@@ -94,32 +632,261 @@ impl FileLogStream {
                         .unwrap_or(0)
                 })
                 .unwrap_or(0);
+            let fields = matches
+                .iter()
+                .filter(|(name, value)| !value.is_empty() && !PREDEFINED_FIELDS.contains(name))
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect();
             ParsedLine {
                 timestamp,
                 loglevel: matches.get("loglevel").map(|s| s.to_string()),
                 message: matches.get("message").unwrap_or("").to_string(),
+                fields,
             }
         } else {
             ParsedLine {
                 timestamp: 0,
                 loglevel: None,
                 message: format!("Failed to parse: {}", line),
+                fields: BTreeMap::new(),
+            }
+        }
+    }
+
+    pub(super) fn open(&mut self) -> std::io::Result<()> {
+        self.year = std::fs::metadata(&self.path)
+            .map(|meta| meta.modified())
+            .map(|maybe_time| {
+                maybe_time
+                    .map(|systime| util::system_time_to_date_time(systime).year())
+                    .unwrap_or(0)
+            })
+            .unwrap_or(0);
+        let file = std::fs::File::open(&self.path)?;
+        if let Ok(meta) = file.metadata() {
+            self.identity = util::file_identity(&meta);
+        }
+        let reader = if self.path.ends_with(".gz") {
+            FileReader::GZIP(BufReader::new(GzDecoder::new(file)))
+        } else {
+            FileReader::PLAIN(BufReader::new(file))
+        };
+        let lines_iter = match self.context.order {
+            Order::Asc => self.open_forward(reader)?,
+            Order::Desc => self.open_backward(reader)?,
+        };
+        self.lines_iter = Some(lines_iter);
+        Ok(())
+    }
+
+    fn open_forward(&self, reader: FileReader) -> std::io::Result<Lines> {
+        let follow = self.watch.is_some() && matches!(reader, FileReader::PLAIN(_));
+        let mut lines_iter = LinesIter::new(reader);
+        lines_iter.follow = follow;
+        if self.tail {
+            lines_iter.seek_end()?;
+        } else if let Some(cursor) = &self.resume_at {
+            lines_iter.skip_to(cursor.offset, cursor.line)?;
+            // a newest-first cursor is at the start of its line, which was sent already
+            if cursor.desc && lines_iter.offset == cursor.offset {
+                lines_iter.next().transpose()?;
             }
+        } else if self.context.from_ms > 0 {
+            let line_pattern = self.line_pattern.clone();
+            let year = self.year;
+            lines_iter.seek_timestamp(self.context.from_ms, |line| {
+                FileLogStream::apply_pattern(line, &line_pattern, year).timestamp
+            })?;
         }
+        Ok(Lines::Forward(Box::new(lines_iter)))
     }
 
-    fn next_line(&mut self) -> Poll<Option<Result<StreamEntry, ApplicationError>>> {
-        let lines_iter = self.lines_iter.as_mut();
-        let lines_iter = lines_iter.unwrap(); // should panic, if this is called with None option
+    fn open_backward(&self, reader: FileReader) -> std::io::Result<Lines> {
+        let mut lines_iter = ReverseLinesIter::new(reader)?;
+        if let Some(cursor) = &self.resume_at {
+            lines_iter.skip_to(cursor.offset, cursor.line);
+            // an oldest-first cursor is at the end of its line, which was sent already
+            if !cursor.desc && lines_iter.offset == cursor.offset {
+                lines_iter.next().transpose()?;
+            }
+        }
+        Ok(Lines::Backward(lines_iter))
+    }
+
+    fn next_line(
+        &mut self,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<StreamEntry, ApplicationError>>> {
+        loop {
+            if let Some(entry) = self.read_rotated() {
+                return Poll::Ready(Some(Ok(entry)));
+            }
+            if let Some(entry) = self.read_entry() {
+                return Poll::Ready(Some(Ok(entry)));
+            }
+            if self.watch.is_some() {
+                match self.reopen_if_rotated() {
+                    Ok(true) => continue,
+                    Ok(false) => {}
+                    Err(e) => {
+                        error!("Failed to follow {}: {:?}", self.path, e);
+                        return Poll::Ready(None);
+                    }
+                }
+            }
+            if self.poll_rotated(cx).is_ready() {
+                continue;
+            }
+            // at the end, watched files are read again once they changed
+            match self.watch.as_mut().map(|watch| watch.poll_change(cx)) {
+                Some(Poll::Ready(())) => continue,
+                Some(Poll::Pending) => return Poll::Pending,
+                None => return Poll::Ready(None),
+            }
+        }
+    }
+
+    /// Continue with the start of the file, if it was truncated as by copytruncate, or with a new file
+    /// found at the path, if it was rotated or replaced otherwise. Called once the file is read to its end.
+    /// The rotated file is still read until it is quiet, see `poll_rotated`.
+    fn reopen_if_rotated(&mut self) -> std::io::Result<bool> {
+        let lines_iter = match &mut self.lines_iter {
+            Some(Lines::Forward(lines_iter)) => lines_iter,
+            _ => return Ok(false),
+        };
+        if let FileReader::PLAIN(reader) = &mut lines_iter.reader {
+            // the lines read before were copied, only lines written after truncating are new
+            if reader.get_ref().metadata()?.len() < lines_iter.offset {
+                info!("{} was truncated, reading from its start", self.path);
+                reader.seek(SeekFrom::Start(0))?;
+                lines_iter.offset = 0;
+                lines_iter.line = Some(0);
+                return Ok(true);
+            }
+        }
+
+        // a missing file is waited for, it may be created again
+        let identity = match fs::metadata(&self.path) {
+            Ok(meta) => util::file_identity(&meta),
+            Err(_) => return Ok(false),
+        };
+        if identity == self.identity {
+            return Ok(false);
+        }
+        info!("{} was rotated, following the new file", self.path);
+        // watched before opening, so no change of the new file is missed
+        let new_watch = self.watch.as_ref().map(Watch::rewatch);
+        let file = std::fs::File::open(&self.path)?;
+        let new_identity = util::file_identity(&file.metadata()?);
+        let mut lines_iter = LinesIter::new(FileReader::PLAIN(BufReader::new(file)));
+        lines_iter.follow = true;
+        if self.rotated.is_some() {
+            warn!(
+                "{} was rotated again, lines appended to its previous file are not read anymore",
+                self.path
+            );
+        }
+        self.rotated = Some(RotatedFile {
+            lines_iter: self
+                .lines_iter
+                .replace(Lines::Forward(Box::new(lines_iter)))
+                .unwrap(),
+            identity: std::mem::replace(&mut self.identity, new_identity),
+            watch: std::mem::replace(&mut self.watch, new_watch),
+            quiet_since: Instant::now(),
+            timer: None,
+        });
+        Ok(true)
+    }
+
+    /// Read a line appended to the rotated file, if any. The file is closed, once it is read
+    /// to its end after the grace period.
+    fn read_rotated(&mut self) -> Option<StreamEntry> {
+        let mut rotated = self.rotated.take()?;
+        let entry = self.read_lines(&mut rotated.lines_iter, rotated.identity);
+        let following = matches!(&rotated.lines_iter, Lines::Forward(iter) if iter.follow);
+        if entry.is_some() {
+            rotated.quiet_since = Instant::now();
+            self.rotated = Some(rotated);
+        } else if following {
+            self.rotated = Some(rotated);
+        } else {
+            debug!("Finished reading the rotated file of {}", self.path);
+        }
+        entry
+    }
+
+    /// Ready if the rotated file may have changed. Once it was quiet for the grace period,
+    /// a last line without line end is read as it is, as it will not be completed anymore.
+    fn poll_rotated(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        let rotated = match self.rotated.as_mut() {
+            Some(rotated) => rotated,
+            None => return Poll::Pending,
+        };
+        if let Some(Poll::Ready(())) = rotated.watch.as_mut().map(|watch| watch.poll_change(cx)) {
+            return Poll::Ready(());
+        }
+        let timer = rotated
+            .timer
+            .get_or_insert_with(|| Box::pin(async_std::task::sleep(ROTATED_CHECK_INTERVAL)));
+        if timer.as_mut().poll(cx).is_pending() {
+            return Poll::Pending;
+        }
+        rotated.timer = None;
+        if rotated.quiet_since.elapsed() >= ROTATED_GRACE_PERIOD {
+            if let Lines::Forward(lines_iter) = &mut rotated.lines_iter {
+                lines_iter.follow = false;
+            }
+        }
+        Poll::Ready(())
+    }
+
+    fn read_entry(&mut self) -> Option<StreamEntry> {
+        let mut lines_iter = self.lines_iter.take().unwrap(); // should panic, if this is called with None option
+        let entry = self.read_lines(&mut lines_iter, self.identity);
+        self.lines_iter = Some(lines_iter);
+        entry
+    }
+
+    fn read_lines(&self, lines_iter: &mut Lines, identity: (u64, u64)) -> Option<StreamEntry> {
         while let Some(nextline) = lines_iter.next() {
             match nextline {
                 Ok(line) => {
+                    if let Some(until) = self.until {
+                        if until.identity == identity && lines_iter.last_position() > until.offset {
+                            return None;
+                        }
+                    }
                     let parsed_line =
                         FileLogStream::apply_pattern(&line, &self.line_pattern, self.year);
-                    if !self.context.matches(&parsed_line) {
-                        continue;
-                    } else {
-                        return Poll::Ready(Some(Ok(StreamEntry { line, parsed_line })));
+                    if let Lines::Backward(_) = lines_iter {
+                        // the remaining lines are older, as far as the file is ordered
+                        if parsed_line.timestamp > 0 && parsed_line.timestamp < self.context.from_ms
+                        {
+                            return None;
+                        }
+                    }
+                    if let Some(is_match) = self.context.select(&parsed_line) {
+                        let cursor = SourceCursor {
+                            source: self.source_id.clone(),
+                            dev: identity.0,
+                            inode: identity.1,
+                            offset: lines_iter.last_position(),
+                            line: lines_iter.line_number(),
+                            desc: matches!(lines_iter, Lines::Backward(_)),
+                        };
+                        return Some(StreamEntry {
+                            line,
+                            parsed_line,
+                            cursor: Some(cursor),
+                            context: !is_match,
+                            marker: None,
+                            repeat: None,
+                            origin: Some(Origin {
+                                line: lines_iter.line_number(),
+                                ..self.origin.clone()
+                            }),
+                        });
                     }
                 }
                 Err(e) => {
@@ -128,48 +895,128 @@ impl FileLogStream {
                 }
             }
         }
+        None
+    }
+}
 
-        if self.watch {
-            Poll::Pending // why is this awakened?
-        } else {
-            Poll::Ready(None)
+impl Stream for FileLogStream {
+    type Item = Result<StreamEntry, ApplicationError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let inner_self = self.get_mut();
+        if inner_self.lines_iter.is_none() {
+            if let Err(e) = inner_self.open() {
+                error!("Failed to open {}: {:?}", inner_self.path, e);
+                return Poll::Ready(None);
+            }
         }
+        inner_self.next_line(cx)
     }
 }
 
-impl Stream for FileLogStream {
+/// Stream of a followed file, whose path is forgotten once it ends.
+struct FollowedFile {
+    stream: LogStream,
+    path: String,
+    followed_paths: Rc<RefCell<HashSet<String>>>,
+}
+
+impl Stream for FollowedFile {
     type Item = Result<StreamEntry, ApplicationError>;
 
-    fn poll_next(self: Pin<&mut Self>, _ctx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
-        let mut inner_self = self.get_mut();
-        match &mut inner_self.lines_iter {
-            Some(_) => inner_self.next_line(),
-            None => {
-                inner_self.year = std::fs::metadata(&inner_self.path)
-                    .map(|meta| meta.modified())
-                    .map(|maybe_time| {
-                        maybe_time
-                            .map(|systime| util::system_time_to_date_time(systime).year())
-                            .unwrap_or(0)
-                    })
-                    .unwrap_or(0);
-                match std::fs::File::open(&inner_self.path) {
-                    Ok(file) => {
-                        let lines_iter = if inner_self.path.ends_with(".gz") {
-                            LinesIter::GZIP(BufReader::new(GzDecoder::new(file)).lines())
-                        } else {
-                            LinesIter::PLAIN(BufReader::new(file).lines())
-                        };
-                        inner_self.lines_iter = Some(lines_iter);
-                        inner_self.next_line()
-                    }
-                    Err(e) => {
-                        error!("Stream error: {:?}", e);
-                        Poll::Ready(None)
-                    }
-                }
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let inner_self = self.get_mut();
+        let next = inner_self.stream.poll_next_unpin(cx);
+        if let Poll::Ready(None) = next {
+            inner_self
+                .followed_paths
+                .borrow_mut()
+                .remove(&inner_self.path);
+        }
+        next
+    }
+}
+
+/// Stream of a followed source, which adds the files matching its pattern once they are created,
+/// merged by timestamp with the files followed already. Files with a rotation index are not added,
+/// their lines were read under their former name. A file is known by its identity only while it is
+/// found at the path it was seen at, as the inode of a deleted file may be reused by a new one.
+/// A followed file created anew at its path is followed by its stream, as after rotation.
+///
+/// Of the files existing when following starts, only the newest one is followed. Lines appended
+/// to the older ones are not read, unless such a file is created anew.
+struct WatchedSource {
+    merge: LogMerge,
+    dir_watch: Watch,
+    file_pattern: Regex,
+    source_id: String,
+    line_pattern: Arc<LinePattern>,
+    context: Arc<LogQueryContext>,
+    tails: TailService,
+    followed_paths: Rc<RefCell<HashSet<String>>>,
+    known_files: HashMap<(u64, u64), (String, Option<SystemTime>)>, // identities with path and creation
+}
+
+impl WatchedSource {
+    fn add_new_files(&mut self) {
+        let files = match FileSource::matching_files(&self.file_pattern, 0) {
+            Ok(files) => files,
+            Err(_) => return,
+        };
+        // a file created anew at the same path may get the same inode, where the filesystem
+        // records the creation time it tells the files apart
+        self.known_files.retain(|identity, (path, created)| {
+            fs::metadata(path)
+                .map(|meta| {
+                    util::file_identity(&meta) == *identity && meta.created().ok() == *created
+                })
+                .unwrap_or(false)
+        });
+        for (path, rotation) in files {
+            let (identity, created) = match fs::metadata(&path) {
+                Ok(meta) if meta.is_file() => (util::file_identity(&meta), meta.created().ok()),
+                _ => continue,
+            };
+            let is_followed = self.followed_paths.borrow().contains(&path);
+            let is_known = self
+                .known_files
+                .insert(identity, (path.clone(), created))
+                .is_some();
+            if is_known || is_followed || rotation > 0 {
+                continue;
             }
+            info!("Following new file {} of source {}", path, self.source_id);
+            let stream =
+                FileLogStream::new(&path, &self.source_id, &self.line_pattern, &self.context)
+                    .with_shared_tail(&self.tails);
+            let stream = self.followed(&path, stream);
+            self.merge.add(stream);
+        }
+    }
+
+    fn followed(&self, path: &str, stream: LogStream) -> LogStream {
+        self.followed_paths.borrow_mut().insert(path.to_string());
+        FollowedFile {
+            stream,
+            path: path.to_string(),
+            followed_paths: self.followed_paths.clone(),
+        }
+        .boxed_local()
+    }
+}
+
+impl Stream for WatchedSource {
+    type Item = Result<StreamEntry, ApplicationError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let inner_self = self.get_mut();
+        while let Poll::Ready(()) = inner_self.dir_watch.poll_change(cx) {
+            inner_self.add_new_files();
         }
+        inner_self
+            .merge
+            .poll_next_unpin(cx)
+            .map(|entry| entry.map(Ok))
     }
 }
 
@@ -177,42 +1024,142 @@ pub struct FileSource;
 
 impl FileSource {
     pub fn create_stream(
+        source_id: &str,
         file_pattern: &Regex,
         line_pattern: &Arc<LinePattern>,
         context: &Arc<LogQueryContext>,
+        tails: &TailService,
     ) -> Result<LogStream, ApplicationError> {
-        let files = Self::resolve_files(&file_pattern, context.from_ms)?;
+        // when resuming, the file of the cursor is looked up among all files, regardless of their age
+        let resume_point = context.resume_point(source_id);
+        let from_ms = if resume_point.is_some() {
+            0
+        } else {
+            context.from_ms
+        };
+        let mut files = Self::resolve_files(&file_pattern, from_ms, context.order)?;
+
+        // when resuming, skip all files before the one the cursor points into
+        let mut resume_at = None;
+        if let Some(cursor) = resume_point {
+            let position = files.iter().position(|file| {
+                fs::metadata(file)
+                    .map(|meta| util::file_identity(&meta) == (cursor.dev, cursor.inode))
+                    .unwrap_or(false)
+            });
+            match position {
+                Some(idx) => {
+                    files.drain(..idx);
+                    resume_at = Some(cursor.clone());
+                }
+                None => {
+                    // resuming from the start would deliver the lines read already once more
+                    warn!("File of cursor for source {} not found", source_id);
+                    return Err(ApplicationError::CursorExpired);
+                }
+            }
+        }
+
         let mut peekable_iter = files.iter().peekable();
-        let mut streams = Vec::<FileLogStream>::new();
+        let mut streams = Vec::<LogStream>::new();
 
         while let Some(file) = peekable_iter.next() {
             let metadata = fs::metadata(&file).map_err(|_| ApplicationError::FailedToReadSource);
 
             let stream = match metadata {
                 Ok(meta) if !meta.is_dir() => {
-                    let fstream = FileLogStream::new(&file, &line_pattern, &context);
-                    // for the last file, add a watch flag if requested
-                    let fstream = if let Some(true) = context.watch {
-                        if let None = peekable_iter.peek() {
-                            fstream.with_watch()
-                        } else {
-                            fstream
-                        }
-                    } else {
-                        fstream
+                    let fstream = FileLogStream::new(&file, source_id, &line_pattern, &context);
+                    let fstream = match resume_at.take() {
+                        Some(cursor) => fstream.with_resume(cursor),
+                        None => fstream,
                     };
-                    Ok(fstream)
+                    // the last file is followed if requested, shared with other followers
+                    let stream = match (context.watch, context.order, peekable_iter.peek()) {
+                        (Some(true), Order::Asc, None) => fstream.with_shared_tail(tails),
+                        (Some(true), _, None) => fstream.with_watch(tails.watcher()).boxed_local(),
+                        _ => fstream.boxed_local(),
+                    };
+                    Ok(stream)
                 }
                 _ => Err(ApplicationError::FailedToReadSource),
             }?;
             streams.push(stream);
         }
 
-        let fullstream = futures::stream::iter(streams).flatten();
-        Ok(fullstream.boxed_local())
+        let stream = futures::stream::iter(streams).flatten().boxed_local();
+        match (context.watch, context.order) {
+            (Some(true), Order::Asc) => Self::watch_new_files(
+                stream,
+                files.last(),
+                source_id,
+                file_pattern,
+                line_pattern,
+                context,
+                tails,
+            ),
+            _ => Ok(stream),
+        }
+    }
+
+    /// Follow the files matching the pattern which are created after the given stream of the existing ones,
+    /// which follows the file at the given path.
+    fn watch_new_files(
+        stream: LogStream,
+        followed: Option<&String>,
+        source_id: &str,
+        file_pattern: &Regex,
+        line_pattern: &Arc<LinePattern>,
+        context: &Arc<LogQueryContext>,
+        tails: &TailService,
+    ) -> Result<LogStream, ApplicationError> {
+        let folder = Path::new(file_pattern.as_str())
+            .parent()
+            .ok_or(ApplicationError::FailedToReadSource)?;
+        // watched before listing the existing files, so none created in between is missed
+        let dir_watch = tails.watcher().watch_dir(&folder.to_string_lossy());
+        let mut known_files = HashMap::new();
+        for (path, _) in Self::matching_files(file_pattern, 0)? {
+            if let Ok(meta) = fs::metadata(&path) {
+                known_files.insert(util::file_identity(&meta), (path, meta.created().ok()));
+            }
+        }
+        let mut watched = WatchedSource {
+            merge: LogMerge::new(vec![]).with_following(),
+            dir_watch,
+            file_pattern: file_pattern.clone(),
+            source_id: source_id.to_string(),
+            line_pattern: line_pattern.clone(),
+            context: context.clone(),
+            tails: tails.clone(),
+            followed_paths: Rc::new(RefCell::new(HashSet::new())),
+            known_files,
+        };
+        let stream = match followed {
+            Some(path) => watched.followed(path, stream),
+            None => stream,
+        };
+        watched.merge.add(stream);
+        Ok(watched.boxed_local())
+    }
+
+    /// Files matching the pattern, from the oldest to the newest or reversed for `Order::Desc`.
+    fn resolve_files(
+        file_pattern: &Regex,
+        from_ms: u128,
+        order: Order,
+    ) -> Result<Vec<String>, ApplicationError> {
+        let mut vec = Self::matching_files(file_pattern, from_ms)?;
+        if order == Order::Desc {
+            vec.reverse();
+        }
+        Ok(vec.into_iter().map(|(p, _)| p).collect())
     }
 
-    fn resolve_files(file_pattern: &Regex, from_ms: u128) -> Result<Vec<String>, ApplicationError> {
+    /// Files matching the pattern with their rotation index, from the oldest to the newest.
+    fn matching_files(
+        file_pattern: &Regex,
+        from_ms: u128,
+    ) -> Result<Vec<(String, i32)>, ApplicationError> {
         let folder = Path::new(file_pattern.as_str())
             .parent()
             .ok_or(ApplicationError::FailedToReadSource)?;
@@ -279,22 +1226,883 @@ impl FileSource {
             }
             ord => ord,
         });
-        Ok(vec.into_iter().map(|(p, _)| p).collect())
+        Ok(vec)
+    }
+
+    /// Files of the source, from the oldest to the newest.
+    pub fn list_files(file_pattern: &Regex) -> Result<Vec<SourceFile>, ApplicationError> {
+        let files = Self::matching_files(file_pattern, 0)?;
+        Ok(files
+            .into_iter()
+            .filter_map(|(path, rotation)| {
+                let meta = fs::metadata(&path).ok().filter(|meta| meta.is_file())?;
+                let name = Path::new(&path).file_name()?.to_str()?.to_string();
+                let modified_ms = meta
+                    .modified()
+                    .ok()
+                    .and_then(|time| time.duration_since(std::time::UNIX_EPOCH).ok())
+                    .map(|duration| duration.as_millis())
+                    .unwrap_or(0);
+                Some(SourceFile {
+                    name,
+                    path,
+                    size: meta.len(),
+                    modified_ms,
+                    rotation,
+                    identity: util::file_identity(&meta),
+                })
+            })
+            .collect())
+    }
+
+    /// A file of the source by name. Only files matching the pattern of the source can be found.
+    pub fn find_file(file_pattern: &Regex, name: &str) -> Result<SourceFile, ApplicationError> {
+        Self::list_files(file_pattern)?
+            .into_iter()
+            .find(|file| file.name == name)
+            .ok_or(ApplicationError::FileNotFound)
+    }
+}
+
+/// A file of a source as offered for download.
+#[derive(Debug, Clone)]
+pub struct SourceFile {
+    pub name: String,
+    pub path: String,
+    pub size: u64,
+    pub modified_ms: u128,
+    pub rotation: i32,
+    pub identity: (u64, u64), // device and inode
+}
+
+impl SourceFile {
+    pub fn is_gzip(&self) -> bool {
+        self.name.ends_with(".gz")
+    }
+
+    /// Read the bytes of the file, the given range of the stored bytes or decompressed content.
+    pub fn open(&self, range: Option<(u64, u64)>, decompress: bool) -> std::io::Result<FileChunks> {
+        let mut file = fs::File::open(&self.path)?;
+        let (reader, remaining): (Box<dyn Read>, Option<u64>) = if decompress {
+            (Box::new(GzDecoder::new(file)), None)
+        } else {
+            // without a range the whole file is sent, which is nothing for an empty file
+            let (start, remaining) = match range {
+                Some((start, end)) => (start, end + 1 - start),
+                None => (0, self.size),
+            };
+            file.seek(SeekFrom::Start(start))?;
+            (Box::new(file), Some(remaining))
+        };
+        Ok(FileChunks { reader, remaining })
+    }
+}
+
+// size of the chunks of file downloads
+const CHUNK_SIZE: usize = 64 * 1024;
+
+/// The bytes of a file in chunks, up to a given number of bytes.
+pub struct FileChunks {
+    reader: Box<dyn Read>,
+    remaining: Option<u64>,
+}
+
+impl Stream for FileChunks {
+    type Item = std::io::Result<bytes::Bytes>;
+
+    fn poll_next(self: Pin<&mut Self>, _ctx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let inner_self = self.get_mut();
+        let size = match inner_self.remaining {
+            Some(0) => return Poll::Ready(None),
+            Some(remaining) => remaining.min(CHUNK_SIZE as u64) as usize,
+            None => CHUNK_SIZE,
+        };
+        let mut buf = vec![0; size];
+        match inner_self.reader.read(&mut buf) {
+            Ok(0) => Poll::Ready(None),
+            Ok(read) => {
+                buf.truncate(read);
+                if let Some(remaining) = &mut inner_self.remaining {
+                    *remaining -= read as u64;
+                }
+                Poll::Ready(Some(Ok(bytes::Bytes::from(buf))))
+            }
+            Err(e) => Poll::Ready(Some(Err(e))),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::log_source::file_source::FileSource;
+    use crate::cursor::Cursor;
+    use crate::data::{ApplicationError, LinePattern, LogQueryContext, Order, StreamEntry};
+    use crate::log_merge::LogMerge;
+    use crate::log_source::file_source::{
+        FileLogStream, FileReader, FileSource, LinesIter, LogStream, ReverseLinesIter, SourceFile,
+        WatchedSource,
+    };
+    use crate::log_source::file_tail::TailService;
+    use crate::log_source::file_watch::{WatchConfig, WatchMode};
+    use async_std::future::{timeout, TimeoutError};
+    use async_std::task;
+    use futures::stream::StreamExt;
     use regex::Regex;
+    use std::cell::RefCell;
+    use std::collections::HashSet;
+    use std::io::Write;
+    use std::rc::Rc;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn demo_line_pattern() -> Arc<LinePattern> {
+        let mut grok = grok::Grok::default();
+        let raw = "%{TIMESTAMP_ISO8601:timestamp} %{LOGLEVEL:loglevel} %{GREEDYDATA:message}";
+        Arc::new(LinePattern {
+            raw: raw.to_string(),
+            grok: Arc::new(grok.compile(raw, true).unwrap()),
+            chrono: Arc::new("%Y-%m-%d %H:%M:%S".to_string()),
+            timezone: chrono_tz::Europe::Berlin,
+            syslog_ts: false,
+        })
+    }
+
+    fn query_context(from_ms: u128, after: Option<Cursor>, order: Order) -> Arc<LogQueryContext> {
+        Arc::new(LogQueryContext {
+            from_ms,
+            to_ms: None,
+            loglevels: None,
+            watch: None,
+            after,
+            before_lines: 0,
+            after_lines: 0,
+            dedupe: None,
+            order,
+        })
+    }
+
+    fn read_demo(after: Option<Cursor>, order: Order) -> Vec<StreamEntry> {
+        read_demo_from(0, after, order)
+    }
+
+    fn read_demo_from(from_ms: u128, after: Option<Cursor>, order: Order) -> Vec<StreamEntry> {
+        let regex = Regex::new(r#"tests/demo\.log(\.(?P<rotation>\d)(\.gz)?)?"#).unwrap();
+        let stream = FileSource::create_stream(
+            "demo",
+            &regex,
+            &demo_line_pattern(),
+            &query_context(from_ms, after, order),
+            &TailService::default(),
+        )
+        .unwrap();
+        task::block_on(
+            stream
+                .map(|entry| entry.unwrap())
+                .collect::<Vec<StreamEntry>>(),
+        )
+    }
+
+    fn read_file(path: &str, from_ms: u128) -> Vec<String> {
+        let stream = FileLogStream::new(
+            path,
+            "test",
+            &demo_line_pattern(),
+            &query_context(from_ms, None, Order::Asc),
+        );
+        task::block_on(
+            stream
+                .map(|entry| entry.unwrap().line)
+                .collect::<Vec<String>>(),
+        )
+    }
+
+    /// Writes a log with a line per given second after 2019-01-01 00:00:00,
+    /// every seventh line is followed by a line without timestamp.
+    fn write_log(name: &str, seconds: &[i64]) -> String {
+        let path =
+            std::env::temp_dir().join(format!("tentacle-{}-{}.log", name, std::process::id()));
+        let start = chrono::NaiveDate::from_ymd(2019, 1, 1).and_hms(0, 0, 0);
+        let mut content = String::new();
+        for (idx, sec) in seconds.iter().enumerate() {
+            let ts = start + chrono::Duration::seconds(*sec);
+            content.push_str(&format!(
+                "{} INFO line {}\n",
+                ts.format("%Y-%m-%d %H:%M:%S"),
+                idx
+            ));
+            if idx % 7 == 0 {
+                content.push_str(&format!("  continuation of line {}\n", idx));
+            }
+        }
+        std::fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn append(path: &str, content: &str) {
+        let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+        file.write_all(content.as_bytes()).unwrap();
+    }
+
+    async fn next_line(stream: &mut LogStream) -> Result<String, TimeoutError> {
+        let entry = timeout(Duration::from_secs(1), stream.next()).await;
+        entry.map(|entry| entry.unwrap().unwrap().line)
+    }
+
+    fn watch_context() -> Arc<LogQueryContext> {
+        Arc::new(LogQueryContext {
+            from_ms: 0,
+            to_ms: None,
+            loglevels: None,
+            watch: Some(true),
+            after: None,
+            before_lines: 0,
+            after_lines: 0,
+            dedupe: None,
+            order: Order::Asc,
+        })
+    }
+
+    /// Stream of watched logs matching the pattern, positioned after their existing lines.
+    fn follow_pattern(pattern: &str, mode: WatchMode, existing: usize) -> LogStream {
+        let tails = TailService::new(WatchConfig {
+            mode,
+            poll_interval: Duration::from_millis(50),
+            buffer_lines: 100,
+        });
+        let mut stream = FileSource::create_stream(
+            "follow",
+            &Regex::new(pattern).unwrap(),
+            &demo_line_pattern(),
+            &watch_context(),
+            &tails,
+        )
+        .unwrap();
+        for _ in 0..existing {
+            assert!(task::block_on(next_line(&mut stream)).is_ok());
+        }
+        stream
+    }
+
+    /// Stream of a watched log, positioned after its existing lines.
+    fn follow(path: &str, mode: WatchMode, existing: usize) -> LogStream {
+        let pattern = format!(r#"{}(\.(?P<rotation>\d))?"#, regex::escape(path));
+        follow_pattern(&pattern, mode, existing)
+    }
+
+    fn log_line(sec: u32) -> String {
+        format!("2019-01-01 00:00:{:02} INFO line {}", sec, sec)
+    }
+
+    /// Follows a log while lines are appended, each must arrive within a second.
+    fn assert_follows(name: &str, mode: WatchMode) {
+        let path = write_log(name, &[0, 1]);
+        let mut stream = follow(&path, mode, 3);
+        task::block_on(async {
+            // appended while the follower waits
+            let appending = path.clone();
+            let writer = std::thread::spawn(move || {
+                std::thread::sleep(Duration::from_millis(100));
+                append(&appending, "2019-01-01 00:00:02 INFO line 2\n");
+            });
+            assert_eq!(
+                next_line(&mut stream).await.unwrap(),
+                "2019-01-01 00:00:02 INFO line 2"
+            );
+            writer.join().unwrap();
+
+            // a partial line is held back until it is complete
+            append(&path, "2019-01-01 00:00:03 INFO li");
+            assert!(next_line(&mut stream).await.is_err());
+            append(&path, "ne 3\n");
+            assert_eq!(
+                next_line(&mut stream).await.unwrap(),
+                "2019-01-01 00:00:03 INFO line 3"
+            );
+        });
+        std::fs::remove_file(path).unwrap();
+    }
+
+    fn bisect(path: &str, from_ms: u128) -> Option<u64> {
+        let line_pattern = demo_line_pattern();
+        let mut reader = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+        LinesIter::bisect(&mut reader, from_ms, &|line: &str| {
+            FileLogStream::apply_pattern(line, &line_pattern, 2019).timestamp
+        })
+        .unwrap()
+    }
+
+    fn assert_same_as_full_scan(path: &str, from_ms_list: &[u128]) {
+        let line_pattern = demo_line_pattern();
+        let full_scan: Vec<(u128, String)> = read_file(path, 0)
+            .into_iter()
+            .map(|line| {
+                let ts = FileLogStream::apply_pattern(&line, &line_pattern, 2019).timestamp;
+                (ts, line)
+            })
+            .collect();
+        for from_ms in from_ms_list {
+            let expected: Vec<String> = full_scan
+                .iter()
+                .filter(|(ts, _)| ts >= from_ms)
+                .map(|(_, line)| line.clone())
+                .collect();
+            assert_eq!(read_file(path, *from_ms), expected);
+        }
+    }
 
     #[test]
     fn test_resolve_files() {
         let regex = Regex::new(r#"tests/demo\.log(\.(?P<rotation>\d)(\.gz)?)?"#).unwrap();
-        let result = FileSource::resolve_files(&regex, 0).unwrap();
+        let result = FileSource::resolve_files(&regex, 0, Order::Asc).unwrap();
         assert_eq!(result.len(), 3);
         assert_eq!(result.get(0), Some(&"tests/demo.log.2.gz".to_string()));
         assert_eq!(result.get(1), Some(&"tests/demo.log.1".to_string()));
         assert_eq!(result.get(2), Some(&"tests/demo.log".to_string()));
+
+        let reversed = FileSource::resolve_files(&regex, 0, Order::Desc).unwrap();
+        assert_eq!(reversed, result.into_iter().rev().collect::<Vec<String>>());
+    }
+
+    #[test]
+    fn test_list_and_download_files() {
+        let regex = Regex::new(r#"tests/demo\.log(\.(?P<rotation>\d)(\.gz)?)?"#).unwrap();
+        let files = FileSource::list_files(&regex).unwrap();
+        let names: Vec<&str> = files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, vec!["demo.log.2.gz", "demo.log.1", "demo.log"]);
+        assert_eq!(files[0].rotation, 2);
+        assert!(files[0].is_gzip());
+
+        let file = FileSource::find_file(&regex, "demo.log").unwrap();
+        let content = std::fs::read(&file.path).unwrap();
+        let read = |range, decompress| {
+            let chunks = file.open(range, decompress).unwrap();
+            task::block_on(chunks.map(|c| c.unwrap()).collect::<Vec<_>>()).concat()
+        };
+        assert_eq!(read(None, false), content);
+        assert_eq!(read(Some((5, 9)), false), &content[5..10]);
+
+        let empty = SourceFile {
+            size: 0,
+            ..file.clone()
+        };
+        let chunks = empty.open(None, false).unwrap();
+        assert!(task::block_on(chunks.collect::<Vec<_>>()).is_empty());
+
+        let gzip = FileSource::find_file(&regex, "demo.log.2.gz").unwrap();
+        let chunks = gzip.open(None, true).unwrap();
+        let decompressed = task::block_on(chunks.map(|c| c.unwrap()).collect::<Vec<_>>()).concat();
+        assert!(String::from_utf8(decompressed)
+            .unwrap()
+            .starts_with("2019-01-01 08:00:01 ERROR demo2line1"));
+
+        assert!(FileSource::find_file(&regex, "integrationtests.yml").is_err());
+        assert!(FileSource::find_file(&regex, "../tests/demo.log").is_err());
+    }
+
+    #[test]
+    fn test_resume_after_cursor() {
+        let all = read_demo(None, Order::Asc);
+        assert!(all.len() > 6);
+
+        // resume within the gzip file, the rotated plain file and the current file
+        for idx in vec![1, 4, all.len() - 2] {
+            let mut cursor = Cursor::default();
+            cursor.advance(all[idx].cursor.as_ref().unwrap());
+            let resumed = read_demo(Some(cursor), Order::Asc);
+            assert_eq!(resumed, all[idx + 1..].to_vec());
+        }
+    }
+
+    #[test]
+    fn test_resume_in_missing_file() {
+        let mut cursor = Cursor::default();
+        let mut source_cursor = read_demo(None, Order::Asc)[0].cursor.clone().unwrap();
+        source_cursor.inode = 0;
+        cursor.advance(&source_cursor);
+        let regex = Regex::new(r#"tests/demo\.log(\.(?P<rotation>\d)(\.gz)?)?"#).unwrap();
+        let result = FileSource::create_stream(
+            "demo",
+            &regex,
+            &demo_line_pattern(),
+            &query_context(0, Some(cursor), Order::Asc),
+            &TailService::default(),
+        );
+        assert!(matches!(result, Err(ApplicationError::CursorExpired)));
+    }
+
+    #[test]
+    fn test_read_backwards() {
+        let open = |path: &str| {
+            let file = std::fs::File::open(path).unwrap();
+            if path.ends_with(".gz") {
+                FileReader::GZIP(std::io::BufReader::new(flate2::read::GzDecoder::new(file)))
+            } else {
+                FileReader::PLAIN(std::io::BufReader::new(file))
+            }
+        };
+        let lines = |path: &str| -> Vec<String> {
+            LinesIter::new(open(path)).map(|l| l.unwrap()).collect()
+        };
+        let reversed = |path: &str| -> Vec<String> {
+            let mut backward: Vec<String> = ReverseLinesIter::new(open(path))
+                .unwrap()
+                .map(|l| l.unwrap())
+                .collect();
+            backward.reverse();
+            backward
+        };
+
+        // spanning several blocks
+        let seconds: Vec<i64> = (0..6000).collect();
+        let path = write_log("backwards", &seconds);
+        assert_eq!(reversed(&path), lines(&path));
+        assert_eq!(
+            reversed("tests/demo.log.2.gz"),
+            lines("tests/demo.log.2.gz")
+        );
+
+        // empty lines, missing line end at the end of file and windows line ends
+        for content in vec!["", "\n", "a", "a\n\nb", "\na\r\nb\r\n"] {
+            std::fs::write(&path, content).unwrap();
+            assert_eq!(reversed(&path), lines(&path), "content {:?}", content);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_line_numbers() {
+        let line_numbers = |entries: &[StreamEntry]| -> Vec<(String, Option<u64>)> {
+            entries
+                .iter()
+                .map(|e| {
+                    let origin = e.origin.as_ref().unwrap();
+                    (origin.file.to_string(), origin.line)
+                })
+                .collect()
+        };
+        let all = line_numbers(&read_demo(None, Order::Asc));
+        let mut expected = Vec::new();
+        for file in &["tests/demo.log.2.gz", "tests/demo.log.1", "tests/demo.log"] {
+            let count = all.iter().filter(|(f, _)| f == file).count() as u64;
+            expected.extend((1..=count).map(|line| (file.to_string(), Some(line))));
+        }
+        assert_eq!(all, expected);
+
+        // unknown for plain files read backwards
+        for (file, line) in line_numbers(&read_demo(None, Order::Desc)) {
+            assert_eq!(line.is_some(), file.ends_with(".gz"));
+        }
+    }
+
+    #[test]
+    fn test_newest_first() {
+        let all = read_demo(None, Order::Asc);
+        let newest_first = read_demo(None, Order::Desc);
+        let lines = |entries: &[StreamEntry]| -> Vec<String> {
+            entries.iter().map(|e| e.line.clone()).collect()
+        };
+        let mut expected = lines(&all);
+        expected.reverse();
+        assert_eq!(lines(&newest_first), expected);
+
+        // resume within the current file, the rotated plain file and the gzip file
+        for idx in vec![1, 4, newest_first.len() - 2] {
+            let mut cursor = Cursor::default();
+            cursor.advance(newest_first[idx].cursor.as_ref().unwrap());
+            let resumed = read_demo(Some(cursor), Order::Desc);
+            assert_eq!(lines(&resumed), lines(&newest_first[idx + 1..]));
+        }
+    }
+
+    #[test]
+    fn test_newest_first_from() {
+        let all = read_demo(None, Order::Asc);
+        let lines = |entries: &[StreamEntry]| -> Vec<String> {
+            entries.iter().map(|e| e.line.clone()).collect()
+        };
+        // in the gzip file, the rotated plain file and the current file
+        for idx in vec![1, 4, all.len() - 2] {
+            let from_ms = all[idx].timestamp();
+            let mut expected: Vec<String> = all
+                .iter()
+                .filter(|e| e.timestamp() >= from_ms)
+                .map(|e| e.line.clone())
+                .collect();
+            expected.reverse();
+            assert_eq!(lines(&read_demo_from(from_ms, None, Order::Desc)), expected);
+        }
+    }
+
+    #[test]
+    fn test_resume_in_other_order() {
+        let all = read_demo(None, Order::Asc);
+        let newest_first = read_demo(None, Order::Desc);
+        let lines = |entries: &[StreamEntry]| -> Vec<String> {
+            entries.iter().map(|e| e.line.clone()).collect()
+        };
+        for idx in vec![1, 4, all.len() - 2] {
+            // the same line read newest first
+            let desc_idx = all.len() - 1 - idx;
+            assert_eq!(newest_first[desc_idx].line, all[idx].line);
+
+            let mut cursor = Cursor::default();
+            cursor.advance(newest_first[desc_idx].cursor.as_ref().unwrap());
+            let resumed = read_demo(Some(cursor), Order::Asc);
+            assert_eq!(lines(&resumed), lines(&all[idx + 1..]));
+
+            let mut cursor = Cursor::default();
+            cursor.advance(all[idx].cursor.as_ref().unwrap());
+            let resumed = read_demo(Some(cursor), Order::Desc);
+            assert_eq!(lines(&resumed), lines(&newest_first[desc_idx + 1..]));
+        }
+    }
+
+    #[test]
+    fn test_seek_timestamp_monotonic() {
+        let seconds: Vec<i64> = (0..6000).map(|i| i / 3).collect();
+        let path = write_log("monotonic", &seconds);
+        // 2019-01-01 00:00:00 Europe/Berlin
+        let start_ms = 1546297200000;
+        let from_ms: Vec<u128> = vec![0, 1, 500, 1000, 1333, 1999, 2000, 3000]
+            .iter()
+            .map(|sec| start_ms + sec * 1000)
+            .collect();
+        assert_same_as_full_scan(&path, &from_ms);
+        assert!(bisect(&path, from_ms[5]).unwrap() > 0);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_seek_timestamp_out_of_order_before_landing() {
+        let mut seconds: Vec<i64> = (0..6000).map(|i| i / 3).collect();
+        let path = write_log("out-of-order", &seconds);
+        let start_ms = 1546297200000;
+        let from_ms = start_ms + 1500 * 1000;
+        let landing = bisect(&path, from_ms).unwrap() as usize;
+        assert!(landing > 0);
+
+        // a later line just before the landing point, which the probes do not see
+        let content = std::fs::read_to_string(&path).unwrap();
+        let landing_line = content[..landing]
+            .lines()
+            .rev()
+            .find_map(|line| line.split(" INFO line ").nth(1))
+            .unwrap();
+        seconds[landing_line.parse::<usize>().unwrap()] = 3000;
+        let path = write_log("out-of-order", &seconds);
+        assert_eq!(bisect(&path, from_ms), None);
+        assert_same_as_full_scan(&path, &[from_ms]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_seek_timestamp_out_of_order_block() {
+        // a block of older lines, larger than the range checked before the landing point,
+        // right behind the first lines newer than `from_ms` and hit by the first probe
+        let seconds: Vec<i64> = (0..12000)
+            .map(|i| {
+                if (5800..8800).contains(&i) {
+                    10
+                } else {
+                    100 + i / 3
+                }
+            })
+            .collect();
+        let path = write_log("out-of-order-block", &seconds);
+        let start_ms = 1546297200000;
+        let from_ms = start_ms + 2000 * 1000;
+        assert_eq!(bisect(&path, from_ms), None);
+        assert_same_as_full_scan(&path, &[from_ms]);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_seek_timestamp_not_monotonic() {
+        let seconds: Vec<i64> = (0..6000).map(|i| (i * 7919) % 6000).collect();
+        let path = write_log("unordered", &seconds);
+        let start_ms = 1546297200000;
+        let from_ms: Vec<u128> = vec![1, 1500, 3000, 5999]
+            .iter()
+            .map(|sec| start_ms + sec * 1000)
+            .collect();
+        assert_same_as_full_scan(&path, &from_ms);
+        assert_eq!(bisect(&path, from_ms[3]), None);
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn test_apply_pattern_fields() {
+        let mut grok = grok::Grok::default();
+        let raw = "%{TIMESTAMP_ISO8601:timestamp} %{IP:client} %{LOGLEVEL:loglevel} %{GREEDYDATA:message}";
+        let line_pattern = LinePattern {
+            grok: Arc::new(grok.compile(raw, true).unwrap()),
+            raw: raw.to_string(),
+            ..(*demo_line_pattern()).clone()
+        };
+        let parsed = FileLogStream::apply_pattern(
+            "2019-01-01 10:00:01 10.0.0.1 INFO hello",
+            &line_pattern,
+            2019,
+        );
+        assert_eq!(parsed.message, "hello");
+        assert_eq!(parsed.field("loglevel"), Some("INFO"));
+        assert_eq!(parsed.field("client"), Some("10.0.0.1"));
+        assert_eq!(parsed.fields.len(), 1);
+    }
+
+    #[test]
+    fn test_apply_pattern_named_groups() {
+        let mut grok = grok::Grok::default();
+        let raw = r"%{TIMESTAMP_ISO8601:timestamp} pid=(?<pid>\d+) %{LOGLEVEL:loglevel} %{GREEDYDATA:message}";
+        let line_pattern = LinePattern {
+            grok: Arc::new(grok.compile(raw, true).unwrap()),
+            raw: raw.to_string(),
+            ..(*demo_line_pattern()).clone()
+        };
+        let parsed = FileLogStream::apply_pattern(
+            "2019-01-01 10:00:01 pid=42 INFO hello",
+            &line_pattern,
+            2019,
+        );
+        assert_eq!(parsed.field("pid"), Some("42"));
+        assert!(crate::util::capture_names(raw).contains(&"pid".to_string()));
+    }
+
+    #[test]
+    fn test_follow_inotify() {
+        assert_follows("follow-inotify", WatchMode::Inotify);
+    }
+
+    #[test]
+    fn test_follow_polling() {
+        assert_follows("follow-polling", WatchMode::Poll);
+    }
+
+    #[test]
+    fn test_follow_rotation() {
+        for (name, mode) in &[
+            ("rotate-inotify", WatchMode::Inotify),
+            ("rotate-polling", WatchMode::Poll),
+        ] {
+            let path = write_log(name, &[0, 1]);
+            let rotated = format!("{}.1", path);
+            let mut stream = follow(&path, *mode, 3);
+            task::block_on(async {
+                // lines written to the old file after renaming it are read before switching
+                append(&path, &format!("{}\n", log_line(2)));
+                std::fs::rename(&path, &rotated).unwrap();
+                append(&rotated, &format!("{}\n", log_line(3)));
+                assert_eq!(next_line(&mut stream).await.unwrap(), log_line(2));
+                assert_eq!(next_line(&mut stream).await.unwrap(), log_line(3));
+                assert!(next_line(&mut stream).await.is_err());
+
+                std::fs::write(&path, format!("{}\n", log_line(4))).unwrap();
+                assert_eq!(next_line(&mut stream).await.unwrap(), log_line(4));
+                append(&path, &format!("{}\n", log_line(5)));
+                assert_eq!(next_line(&mut stream).await.unwrap(), log_line(5));
+            });
+            std::fs::remove_file(path).unwrap();
+            std::fs::remove_file(rotated).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_follow_rotation_before_reopen() {
+        for (name, mode) in &[
+            ("reopen-inotify", WatchMode::Inotify),
+            ("reopen-polling", WatchMode::Poll),
+        ] {
+            let path = write_log(name, &[0, 1]);
+            let rotated = format!("{}.1", path);
+            let mut stream = follow(&path, *mode, 3);
+            task::block_on(async {
+                // the writer appends to the renamed file until it reopens its log
+                std::fs::rename(&path, &rotated).unwrap();
+                std::fs::write(&path, format!("{}\n", log_line(2))).unwrap();
+                assert_eq!(next_line(&mut stream).await.unwrap(), log_line(2));
+                append(&rotated, &format!("{}\n", log_line(3)));
+                assert_eq!(next_line(&mut stream).await.unwrap(), log_line(3));
+
+                // a last partial line of the rotated file is read once it is quiet
+                append(&rotated, &log_line(4));
+                append(&path, &format!("{}\n", log_line(5)));
+                assert_eq!(next_line(&mut stream).await.unwrap(), log_line(5));
+                let entry = timeout(Duration::from_secs(4), stream.next()).await;
+                assert_eq!(entry.unwrap().unwrap().unwrap().line, log_line(4));
+                assert!(next_line(&mut stream).await.is_err());
+            });
+            std::fs::remove_file(path).unwrap();
+            std::fs::remove_file(rotated).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_join_tail_after_rotation() {
+        let path = write_log("join-rotated", &[0, 1]);
+        let rotated = format!("{}.1", path);
+        let pattern =
+            Regex::new(&format!(r#"{}(\.(?P<rotation>\d))?"#, regex::escape(&path))).unwrap();
+        // the tail notices the rotation only after the second follower joined
+        let tails = TailService::new(WatchConfig {
+            mode: WatchMode::Poll,
+            poll_interval: Duration::from_millis(500),
+            buffer_lines: 100,
+        });
+        let follow = || {
+            FileSource::create_stream(
+                "join",
+                &pattern,
+                &demo_line_pattern(),
+                &watch_context(),
+                &tails,
+            )
+            .unwrap()
+        };
+        let mut first = follow();
+        task::block_on(async {
+            for _ in 0..3 {
+                assert!(next_line(&mut first).await.is_ok());
+            }
+            std::fs::rename(&path, &rotated).unwrap();
+            std::fs::write(&path, format!("{}\n", log_line(2))).unwrap();
+            let mut second = follow();
+            let mut lines = Vec::new();
+            while let Ok(line) = next_line(&mut second).await {
+                lines.push(line);
+            }
+            assert_eq!(lines.len(), 4);
+            assert_eq!(lines[3], log_line(2));
+            assert_eq!(next_line(&mut first).await.unwrap(), log_line(2));
+        });
+        std::fs::remove_file(path).unwrap();
+        std::fs::remove_file(rotated).unwrap();
+    }
+
+    #[test]
+    fn test_follow_copytruncate() {
+        for (name, mode) in &[
+            ("truncate-inotify", WatchMode::Inotify),
+            ("truncate-polling", WatchMode::Poll),
+        ] {
+            let path = write_log(name, &[0, 1]);
+            let copy = format!("{}.1", path);
+            let mut stream = follow(&path, *mode, 3);
+            task::block_on(async {
+                std::fs::copy(&path, &copy).unwrap();
+                std::fs::OpenOptions::new()
+                    .write(true)
+                    .open(&path)
+                    .unwrap()
+                    .set_len(0)
+                    .unwrap();
+                append(&path, &format!("{}\n", log_line(2)));
+                assert_eq!(next_line(&mut stream).await.unwrap(), log_line(2));
+                assert!(next_line(&mut stream).await.is_err());
+            });
+            std::fs::remove_file(path).unwrap();
+            std::fs::remove_file(copy).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_follow_new_files() {
+        for (name, mode) in &[
+            ("new-inotify", WatchMode::Inotify),
+            ("new-polling", WatchMode::Poll),
+        ] {
+            let dir =
+                std::env::temp_dir().join(format!("tentacle-{}-{}", name, std::process::id()));
+            std::fs::create_dir(&dir).unwrap();
+            let file = |name: &str| dir.join(name).to_str().unwrap().to_string();
+            std::fs::write(file("app-1.log"), format!("{}\n", log_line(0))).unwrap();
+            // the folder is not a pattern
+            let pattern = format!(
+                r#"{}/app-\d+\.log(\.(?P<rotation>\d))?"#,
+                dir.to_str().unwrap()
+            );
+            let mut stream = follow_pattern(&pattern, *mode, 1);
+            task::block_on(async {
+                std::fs::write(file("app-2.log"), format!("{}\n", log_line(1))).unwrap();
+                assert_eq!(next_line(&mut stream).await.unwrap(), log_line(1));
+
+                // lines of new and followed files are merged by timestamp
+                std::fs::write(
+                    file("app-3.log"),
+                    format!("{}\n{}\n", log_line(2), log_line(4)),
+                )
+                .unwrap();
+                append(&file("app-2.log"), &format!("{}\n", log_line(3)));
+                std::thread::sleep(Duration::from_millis(300));
+                for sec in 2..5 {
+                    assert_eq!(next_line(&mut stream).await.unwrap(), log_line(sec));
+                }
+
+                // rotated files were read already
+                std::fs::rename(file("app-3.log"), file("app-3.log.1")).unwrap();
+                std::fs::copy(file("app-1.log"), file("app-1.log.1")).unwrap();
+                assert!(next_line(&mut stream).await.is_err());
+            });
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_follow_recreated_older_file() {
+        let dir = std::env::temp_dir().join(format!("tentacle-recreated-{}", std::process::id()));
+        std::fs::create_dir(&dir).unwrap();
+        let file = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        std::fs::write(file("app-1.log"), format!("{}\n", log_line(0))).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        std::fs::write(file("app-2.log"), format!("{}\n", log_line(1))).unwrap();
+        let pattern = format!(r#"{}/app-\d+\.log"#, dir.to_str().unwrap());
+        let mut stream = follow_pattern(&pattern, WatchMode::Inotify, 2);
+        task::block_on(async {
+            // only the newest of the existing files is followed
+            append(&file("app-1.log"), &format!("{}\n", log_line(2)));
+            assert!(next_line(&mut stream).await.is_err());
+
+            std::fs::remove_file(file("app-1.log")).unwrap();
+            std::fs::write(file("app-1.log"), format!("{}\n", log_line(3))).unwrap();
+            assert_eq!(next_line(&mut stream).await.unwrap(), log_line(3));
+            append(&file("app-1.log"), &format!("{}\n", log_line(4)));
+            assert_eq!(next_line(&mut stream).await.unwrap(), log_line(4));
+            assert!(next_line(&mut stream).await.is_err());
+        });
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_follow_new_file_with_reused_inode() {
+        let dir = std::env::temp_dir().join(format!("tentacle-reused-{}", std::process::id()));
+        std::fs::create_dir(&dir).unwrap();
+        let file = |name: &str| dir.join(name).to_str().unwrap().to_string();
+        std::fs::write(file("app-2.tmp"), format!("{}\n", log_line(1))).unwrap();
+        let meta = std::fs::metadata(file("app-2.tmp")).unwrap();
+        let identity = crate::util::file_identity(&meta);
+
+        let tails = TailService::default();
+        let mut source = WatchedSource {
+            merge: LogMerge::new(vec![]).with_following(),
+            dir_watch: tails.watcher().watch_dir(dir.to_str().unwrap()),
+            file_pattern: Regex::new(&format!(r#"{}/app-\d+\.log"#, dir.to_str().unwrap()))
+                .unwrap(),
+            source_id: "reused".to_string(),
+            line_pattern: demo_line_pattern(),
+            context: watch_context(),
+            tails: tails.clone(),
+            followed_paths: Rc::new(RefCell::new(HashSet::new())),
+            // the inode of a deleted file is reused by the new one
+            known_files: vec![(identity, (file("app-1.log"), meta.created().ok()))]
+                .into_iter()
+                .collect(),
+        };
+        std::fs::rename(file("app-2.tmp"), file("app-2.log")).unwrap();
+        source.add_new_files();
+        task::block_on(async {
+            let entry = timeout(Duration::from_secs(1), source.next()).await;
+            assert_eq!(entry.unwrap().unwrap().unwrap().line, log_line(1));
+        });
+        let known = source.known_files.get(&identity).map(|(path, _)| path);
+        assert_eq!(known, Some(&file("app-2.log")));
+        std::fs::remove_dir_all(dir).unwrap();
     }
 }
@@ -0,0 +1,448 @@
+use crate::data::{
+    ApplicationError, LinePattern, LogQueryContext, Marker, MarkerReason, Order, ParsedLine,
+    StreamEntry,
+};
+use crate::log_source::file_source::{FileLogStream, FilePosition};
+use crate::log_source::file_watch::{FileWatcher, WatchConfig};
+use core::pin::Pin;
+use core::task::Context;
+use futures::stream::{Stream, StreamExt};
+use futures::task::{Poll, Waker};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+
+enum Queued {
+    Entry(Box<StreamEntry>),
+    Dropped(u64), // number of entries dropped at this point, as the queue was full
+}
+
+#[derive(Default)]
+struct QueueState {
+    items: VecDeque<Queued>,
+    waker: Option<Waker>,
+    ended: bool,
+}
+
+// entries of a tail waiting for one subscriber, filtered by its context
+struct Queue {
+    capacity: usize,
+    context: Arc<LogQueryContext>,
+    state: Mutex<QueueState>,
+}
+
+impl Queue {
+    /// Queue the entry, if it is selected by the context of the subscriber.
+    /// Filtered before queueing, so entries left out do not take up the capacity.
+    fn push(&self, entry: &StreamEntry) {
+        let is_match = match self.context.select(&entry.parsed_line) {
+            Some(is_match) => is_match,
+            None => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        if state.items.len() < self.capacity {
+            let entry = StreamEntry {
+                context: !is_match,
+                ..entry.clone()
+            };
+            state.items.push_back(Queued::Entry(Box::new(entry)));
+        } else if let Some(Queued::Dropped(dropped)) = state.items.back_mut() {
+            *dropped += 1;
+        } else {
+            state.items.push_back(Queued::Dropped(1));
+        }
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+
+    fn end(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.ended = true;
+        if let Some(waker) = state.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+struct TailState {
+    position: FilePosition, // after the lines read so far from the file currently at the path
+    subscribers: Vec<Arc<Queue>>,
+    reader: Option<Waker>,
+    closed: bool, // no subscribers are left, so the reader stops
+    ended: bool,  // the reader stopped on its own, e.g. on an error
+}
+
+// a followed file, read by a thread of its own
+struct Tail {
+    state: Mutex<TailState>,
+}
+
+impl Tail {
+    /// Deliver the entry to all subscribers. The position is the one of the reader afterwards,
+    /// lines of a rotated file are read along with the file at the path.
+    fn broadcast(&self, entry: StreamEntry, position: FilePosition) {
+        let mut state = self.state.lock().unwrap();
+        state.position = position;
+        for queue in &state.subscribers {
+            queue.push(&entry);
+        }
+    }
+
+    fn end(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.ended = true;
+        state.subscribers.iter().for_each(|queue| queue.end());
+    }
+}
+
+type TailKey = (String, String); // source id and path
+
+/// Reads and parses each followed file once, for all requests following it.
+/// Entries are broadcast to bounded queues of the subscribers, a subscriber falling behind
+/// by more than `watch.buffer_lines` gets a marker in place of the dropped entries.
+#[derive(Clone)]
+pub struct TailService {
+    watcher: FileWatcher,
+    buffer_lines: usize,
+    tails: Arc<Mutex<HashMap<TailKey, Arc<Tail>>>>,
+}
+
+impl Default for TailService {
+    fn default() -> Self {
+        TailService::new(WatchConfig::default())
+    }
+}
+
+impl TailService {
+    pub fn new(config: WatchConfig) -> TailService {
+        TailService {
+            watcher: FileWatcher::new(config),
+            buffer_lines: config.buffer_lines,
+            tails: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    pub fn watcher(&self) -> &FileWatcher {
+        &self.watcher
+    }
+
+    /// Follow the lines written to the file from now on, filtered by the given context.
+    /// The subscription starts at the returned position, lines before are read by the subscriber.
+    pub(super) fn subscribe(
+        &self,
+        source_id: &str,
+        path: &str,
+        line_pattern: &Arc<LinePattern>,
+        context: &Arc<LogQueryContext>,
+    ) -> std::io::Result<(Subscription, FilePosition)> {
+        let key = (source_id.to_string(), path.to_string());
+        let mut tails = self.tails.lock().unwrap();
+        let tail = match tails.get(&key) {
+            Some(tail) if !tail.state.lock().unwrap().ended => tail.clone(),
+            _ => {
+                let tail = self.start(source_id, path, line_pattern)?;
+                tails.insert(key.clone(), tail.clone());
+                tail
+            }
+        };
+        let queue = Arc::new(Queue {
+            capacity: self.buffer_lines,
+            context: context.clone(),
+            state: Mutex::new(QueueState::default()),
+        });
+        let position = {
+            let mut state = tail.state.lock().unwrap();
+            state.subscribers.push(queue.clone());
+            state.position
+        };
+        let subscription = Subscription {
+            queue,
+            tail,
+            key,
+            tails: self.tails.clone(),
+            last_timestamp: 0,
+        };
+        Ok((subscription, position))
+    }
+
+    fn start(
+        &self,
+        source_id: &str,
+        path: &str,
+        line_pattern: &Arc<LinePattern>,
+    ) -> std::io::Result<Arc<Tail>> {
+        // all lines are broadcast, subscribers filter them
+        let context = Arc::new(LogQueryContext {
+            from_ms: 0,
+            to_ms: None,
+            loglevels: None,
+            watch: Some(true),
+            after: None,
+            before_lines: 0,
+            after_lines: 0,
+            dedupe: None,
+            order: Order::Asc,
+        });
+        let mut stream = FileLogStream::new(path, source_id, line_pattern, &context)
+            .with_tail()
+            .with_watch(&self.watcher);
+        stream.open()?;
+        let tail = Arc::new(Tail {
+            state: Mutex::new(TailState {
+                position: stream.position(),
+                subscribers: Vec::new(),
+                reader: None,
+                closed: false,
+                ended: false,
+            }),
+        });
+        debug!(
+            "Start reading {} for followers of source {}",
+            path, source_id
+        );
+        let reading = tail.clone();
+        std::thread::spawn(move || read(stream, reading));
+        Ok(tail)
+    }
+}
+
+fn read(mut stream: FileLogStream, tail: Arc<Tail>) {
+    futures::executor::block_on(futures::future::poll_fn(|cx| loop {
+        {
+            let mut state = tail.state.lock().unwrap();
+            if state.closed {
+                return Poll::Ready(());
+            }
+            state.reader = Some(cx.waker().clone());
+        }
+        match stream.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(entry))) => tail.broadcast(entry, stream.position()),
+            Poll::Ready(_) => {
+                tail.end();
+                return Poll::Ready(());
+            }
+            Poll::Pending => {
+                // the reader may have switched to a new file at the path
+                tail.state.lock().unwrap().position = stream.position();
+                return Poll::Pending;
+            }
+        }
+    }));
+}
+
+/// Entries of a shared tail for one follower, unsubscribed when dropped.
+pub(super) struct Subscription {
+    queue: Arc<Queue>,
+    tail: Arc<Tail>,
+    key: TailKey,
+    tails: Arc<Mutex<HashMap<TailKey, Arc<Tail>>>>,
+    last_timestamp: u128,
+}
+
+impl Subscription {
+    fn marker(&self, dropped: u64) -> StreamEntry {
+        let reason = MarkerReason::Dropped;
+        StreamEntry {
+            line: reason.message().to_string(),
+            parsed_line: ParsedLine {
+                timestamp: self.last_timestamp,
+                loglevel: Some("WARN".to_string()),
+                message: reason.message().to_string(),
+                fields: Default::default(),
+            },
+            cursor: None,
+            context: false,
+            marker: Some(Marker {
+                reason,
+                skipped: Some(dropped),
+            }),
+            repeat: None,
+            origin: None,
+        }
+    }
+}
+
+impl Stream for Subscription {
+    type Item = Result<StreamEntry, ApplicationError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let queued = {
+            let mut state = self.queue.state.lock().unwrap();
+            match state.items.pop_front() {
+                Some(queued) => queued,
+                None if state.ended => return Poll::Ready(None),
+                None => {
+                    state.waker = Some(cx.waker().clone());
+                    return Poll::Pending;
+                }
+            }
+        };
+        match queued {
+            Queued::Entry(entry) => {
+                if entry.parsed_line.timestamp > 0 {
+                    self.last_timestamp = entry.parsed_line.timestamp;
+                }
+                Poll::Ready(Some(Ok(*entry)))
+            }
+            Queued::Dropped(dropped) => Poll::Ready(Some(Ok(self.marker(dropped)))),
+        }
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        let mut tails = self.tails.lock().unwrap();
+        let mut state = self.tail.state.lock().unwrap();
+        state
+            .subscribers
+            .retain(|queue| !Arc::ptr_eq(queue, &self.queue));
+        if state.subscribers.is_empty() {
+            state.closed = true;
+            if let Some(reader) = state.reader.take() {
+                reader.wake();
+            }
+            // an ended tail may have been replaced already
+            if let Some(tail) = tails.get(&self.key) {
+                if Arc::ptr_eq(tail, &self.tail) {
+                    tails.remove(&self.key);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{LinePattern, LogQueryContext, MarkerReason, Order, StreamEntry};
+    use crate::log_source::file_tail::{Subscription, TailService};
+    use crate::log_source::file_watch::{WatchConfig, WatchMode};
+    use async_std::future::timeout;
+    use async_std::task;
+    use futures::stream::StreamExt;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn line_pattern() -> Arc<LinePattern> {
+        let mut grok = grok::Grok::default();
+        let raw = "%{TIMESTAMP_ISO8601:timestamp} %{LOGLEVEL:loglevel} %{GREEDYDATA:message}";
+        Arc::new(LinePattern {
+            raw: raw.to_string(),
+            grok: Arc::new(grok.compile(raw, true).unwrap()),
+            chrono: Arc::new("%Y-%m-%d %H:%M:%S".to_string()),
+            timezone: chrono_tz::UTC,
+            syslog_ts: false,
+        })
+    }
+
+    fn context(loglevels: Option<Vec<String>>) -> Arc<LogQueryContext> {
+        Arc::new(LogQueryContext {
+            from_ms: 0,
+            to_ms: None,
+            loglevels,
+            watch: Some(true),
+            after: None,
+            before_lines: 0,
+            after_lines: 0,
+            dedupe: None,
+            order: Order::Asc,
+        })
+    }
+
+    fn service(buffer_lines: usize) -> TailService {
+        TailService::new(WatchConfig {
+            mode: WatchMode::Inotify,
+            poll_interval: Duration::from_millis(50),
+            buffer_lines,
+        })
+    }
+
+    fn temp_log(name: &str) -> String {
+        let path =
+            std::env::temp_dir().join(format!("tentacle-{}-{}.log", name, std::process::id()));
+        std::fs::write(&path, "2019-01-01 00:00:00 INFO existing\n").unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn append(path: &str, level: &str, sec: u32) {
+        let mut file = std::fs::OpenOptions::new().append(true).open(path).unwrap();
+        writeln!(file, "2019-01-01 00:00:{:02} {} line {}", sec, level, sec).unwrap();
+    }
+
+    fn next(subscription: &mut Subscription) -> StreamEntry {
+        let entry = timeout(Duration::from_secs(1), subscription.next());
+        task::block_on(entry).unwrap().unwrap().unwrap()
+    }
+
+    #[test]
+    fn test_shared_by_followers() {
+        let path = temp_log("shared");
+        let tails = service(100);
+        let (mut all, position) = tails
+            .subscribe("shared", &path, &line_pattern(), &context(None))
+            .unwrap();
+        let errors = Some(vec!["ERROR".to_string()]);
+        let (mut filtered, same_position) = tails
+            .subscribe("shared", &path, &line_pattern(), &context(errors))
+            .unwrap();
+        assert_eq!(position, same_position);
+        assert_eq!(tails.tails.lock().unwrap().len(), 1);
+
+        append(&path, "INFO", 1);
+        append(&path, "ERROR", 2);
+        assert_eq!(next(&mut all).parsed_line.message, "line 1");
+        assert_eq!(next(&mut all).parsed_line.message, "line 2");
+        assert_eq!(next(&mut filtered).parsed_line.message, "line 2");
+
+        drop(all);
+        assert_eq!(tails.tails.lock().unwrap().len(), 1);
+        drop(filtered);
+        assert!(tails.tails.lock().unwrap().is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_dropped_marker() {
+        let path = temp_log("dropped");
+        let tails = service(2);
+        let (mut subscription, _) = tails
+            .subscribe("dropped", &path, &line_pattern(), &context(None))
+            .unwrap();
+        for sec in 1..6 {
+            append(&path, "INFO", sec);
+        }
+        // the follower does not read meanwhile
+        std::thread::sleep(Duration::from_millis(300));
+        assert_eq!(next(&mut subscription).parsed_line.message, "line 1");
+        assert_eq!(next(&mut subscription).parsed_line.message, "line 2");
+        let marker = next(&mut subscription);
+        let reason = marker.marker.as_ref().map(|marker| marker.reason);
+        assert_eq!(reason, Some(MarkerReason::Dropped));
+        assert_eq!(marker.marker.unwrap().skipped, Some(3));
+        assert_eq!(marker.parsed_line.timestamp, 1_546_300_802_000);
+
+        append(&path, "INFO", 6);
+        assert_eq!(next(&mut subscription).parsed_line.message, "line 6");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_filtered_before_queueing() {
+        let path = temp_log("filtered");
+        let tails = service(2);
+        let errors = Some(vec!["ERROR".to_string()]);
+        let (mut subscription, _) = tails
+            .subscribe("filtered", &path, &line_pattern(), &context(errors))
+            .unwrap();
+        for sec in 1..6 {
+            append(&path, "INFO", sec);
+        }
+        append(&path, "ERROR", 6);
+        // the follower does not read meanwhile
+        std::thread::sleep(Duration::from_millis(300));
+        let entry = next(&mut subscription);
+        assert_eq!(entry.marker, None);
+        assert_eq!(entry.parsed_line.message, "line 6");
+        std::fs::remove_file(path).unwrap();
+    }
+}
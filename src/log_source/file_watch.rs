@@ -0,0 +1,412 @@
+use core::task::Context;
+use futures::task::Poll;
+use futures::task::Waker;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask};
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::os::unix::io::AsRawFd;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::time::{Duration, SystemTime};
+
+// events of a watched file which may let a follower read further
+const WATCH_MASK: WatchMask = WatchMask::from_bits_truncate(
+    WatchMask::MODIFY.bits()
+        | WatchMask::ATTRIB.bits()
+        | WatchMask::CLOSE_WRITE.bits()
+        | WatchMask::MOVE_SELF.bits()
+        | WatchMask::DELETE_SELF.bits(),
+);
+
+// longest wait for inotify events, after which the reading thread checks whether the watcher is gone
+const INOTIFY_TIMEOUT: Duration = Duration::from_secs(1);
+
+// events of the directory of a watched file, when a file is created at its path as by log rotation
+const DIR_WATCH_MASK: WatchMask =
+    WatchMask::from_bits_truncate(WatchMask::CREATE.bits() | WatchMask::MOVED_TO.bits());
+
+/// How watched files are checked for changes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchMode {
+    Inotify, // notified by the kernel, falling back to polling where inotify is not available
+    Poll,    // checked periodically
+}
+
+/// Following of watched sources, configured by `watch`.
+#[derive(Debug, Clone, Copy)]
+pub struct WatchConfig {
+    pub mode: WatchMode,
+    pub poll_interval: Duration,
+    pub buffer_lines: usize, // entries queued for each follower of a shared file
+}
+
+impl Default for WatchConfig {
+    fn default() -> Self {
+        WatchConfig {
+            mode: WatchMode::Inotify,
+            poll_interval: Duration::from_millis(1000),
+            buffer_lines: 10000,
+        }
+    }
+}
+
+impl WatchConfig {
+    pub fn from_settings(settings: &config::Config) -> WatchConfig {
+        let default = WatchConfig::default();
+        WatchConfig {
+            mode: match settings.get_str("watch.mode") {
+                Ok(mode) if mode == "poll" => WatchMode::Poll,
+                Ok(mode) if mode == "inotify" => WatchMode::Inotify,
+                Ok(mode) => panic!("Invalid watch mode: {}", mode),
+                Err(_) => default.mode,
+            },
+            poll_interval: settings
+                .get_int("watch.poll_interval_ms")
+                .map(|ms| Duration::from_millis(ms.max(10) as u64))
+                .unwrap_or(default.poll_interval),
+            buffer_lines: settings
+                .get_int("watch.buffer_lines")
+                .map(|lines| lines.max(1) as usize)
+                .unwrap_or(default.buffer_lines),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct FileState {
+    len: u64,
+    modified: Option<SystemTime>,
+    identity: (u64, u64),
+}
+
+fn file_state(path: &str) -> Option<FileState> {
+    let meta = std::fs::metadata(path).ok()?;
+    Some(FileState {
+        len: meta.len(),
+        modified: meta.modified().ok(),
+        identity: crate::util::file_identity(&meta),
+    })
+}
+
+/// Whether the file is on a network filesystem, where changes by other hosts are not notified.
+#[cfg(target_os = "linux")]
+fn is_network_fs(path: &str) -> bool {
+    // magic numbers of nfs, cifs, smb2, smb and ceph as in statfs(2)
+    const NETWORK_FS: [u64; 5] = [0x6969, 0xFF53_4D42, 0xFE53_4D42, 0x517B, 0x00C3_6400];
+    let path = match std::ffi::CString::new(path) {
+        Ok(path) => path,
+        Err(_) => return false,
+    };
+    let mut stat: libc::statfs = unsafe { std::mem::zeroed() };
+    if unsafe { libc::statfs(path.as_ptr(), &mut stat) } != 0 {
+        return false;
+    }
+    NETWORK_FS.contains(&(stat.f_type as u64 & 0xFFFF_FFFF))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn is_network_fs(_path: &str) -> bool {
+    false
+}
+
+// a watched file as shared with the notifying threads
+struct Slot {
+    path: String,
+    dir: bool, // notified about created entries, not about changes of itself
+    changes: AtomicU64,
+    waker: Mutex<Option<Waker>>,
+    polled: Mutex<Option<FileState>>, // state of the last poll, if polled
+}
+
+impl Slot {
+    fn notify(&self) {
+        self.changes.fetch_add(1, Ordering::SeqCst);
+        if let Some(waker) = self.waker.lock().unwrap().take() {
+            waker.wake();
+        }
+    }
+}
+
+// a slot notified by an inotify watch, of the file itself or of its directory
+struct Watched {
+    slot: Arc<Slot>,
+    name: Option<OsString>, // name of the file within the watched directory
+}
+
+#[derive(Default)]
+struct Registry {
+    inotify: Option<inotify::Watches>, // None until first used or if inotify is not available
+    inotify_failed: bool,
+    watched: HashMap<WatchDescriptor, Vec<Watched>>,
+    polled: Vec<Arc<Slot>>,
+    polling: bool, // whether the polling thread runs
+}
+
+impl Registry {
+    fn add(&mut self, wd: WatchDescriptor, slot: &Arc<Slot>, name: Option<OsString>) {
+        let slot = slot.clone();
+        self.watched
+            .entry(wd)
+            .or_default()
+            .push(Watched { slot, name });
+    }
+
+    fn remove(&mut self, slot: &Arc<Slot>) {
+        self.polled.retain(|polled| !Arc::ptr_eq(polled, slot));
+        let mut unused = Vec::new();
+        for (wd, watched) in self.watched.iter_mut() {
+            watched.retain(|w| !Arc::ptr_eq(&w.slot, slot));
+            if watched.is_empty() {
+                unused.push(wd.clone());
+            }
+        }
+        for wd in unused {
+            self.watched.remove(&wd);
+            if let Some(watches) = self.inotify.as_mut() {
+                if let Err(e) = watches.remove(wd) {
+                    debug!("Failed to remove watch of {}: {}", slot.path, e);
+                }
+            }
+        }
+    }
+}
+
+struct Shared {
+    config: WatchConfig,
+    registry: Mutex<Registry>,
+}
+
+/// Notifies followers of files about changes, by inotify or by polling the files.
+/// The notifying threads are started with the first watch and end within a second or the poll interval
+/// after the watcher and all its watches are dropped.
+#[derive(Clone)]
+pub struct FileWatcher {
+    shared: Arc<Shared>,
+}
+
+impl Default for FileWatcher {
+    fn default() -> Self {
+        FileWatcher::new(WatchConfig::default())
+    }
+}
+
+impl FileWatcher {
+    pub fn new(config: WatchConfig) -> FileWatcher {
+        FileWatcher {
+            shared: Arc::new(Shared {
+                config,
+                registry: Mutex::new(Registry::default()),
+            }),
+        }
+    }
+
+    /// Watch a file for changes, until the returned watch is dropped.
+    pub fn watch(&self, path: &str) -> Watch {
+        self.register(path, false)
+    }
+
+    /// Watch a directory for files created or moved into it, until the returned watch is dropped.
+    pub fn watch_dir(&self, path: &str) -> Watch {
+        self.register(path, true)
+    }
+
+    fn register(&self, path: &str, dir: bool) -> Watch {
+        let slot = Arc::new(Slot {
+            path: path.to_string(),
+            dir,
+            changes: AtomicU64::new(0),
+            waker: Mutex::new(None),
+            polled: Mutex::new(None),
+        });
+        let mut registry = self.shared.registry.lock().unwrap();
+        let notified = self.shared.config.mode == WatchMode::Inotify
+            && !is_network_fs(path)
+            && self.add_inotify(&mut registry, &slot);
+        if !notified {
+            *slot.polled.lock().unwrap() = file_state(path);
+            registry.polled.push(slot.clone());
+            if !registry.polling {
+                registry.polling = true;
+                let shared = Arc::downgrade(&self.shared);
+                let interval = self.shared.config.poll_interval;
+                std::thread::spawn(move || poll_files(shared, interval));
+            }
+        }
+        Watch {
+            slot,
+            shared: self.shared.clone(),
+            seen: 0,
+        }
+    }
+
+    fn add_inotify(&self, registry: &mut Registry, slot: &Arc<Slot>) -> bool {
+        if registry.inotify.is_none() && !registry.inotify_failed {
+            match Inotify::init() {
+                Ok(inotify) => {
+                    registry.inotify = Some(inotify.watches());
+                    let shared = Arc::downgrade(&self.shared);
+                    std::thread::spawn(move || read_events(inotify, shared));
+                }
+                Err(e) => {
+                    warn!("Inotify is not available, polling watched files: {}", e);
+                    registry.inotify_failed = true;
+                }
+            }
+        }
+        let watches = match registry.inotify.as_mut() {
+            Some(watches) => watches,
+            None => return false,
+        };
+        let path = Path::new(&slot.path);
+        if slot.dir {
+            return match watches.add(path, DIR_WATCH_MASK) {
+                Ok(wd) => {
+                    registry.add(wd, slot, None);
+                    true
+                }
+                Err(e) => {
+                    warn!("Failed to watch {}, polling instead: {}", slot.path, e);
+                    false
+                }
+            };
+        }
+        let dir = match path.parent() {
+            Some(dir) if dir.as_os_str().is_empty() => Path::new("."),
+            Some(dir) => dir,
+            None => return false,
+        };
+        let watched = watches.add(path, WATCH_MASK).and_then(|file_wd| {
+            let dir_wd = watches.add(dir, DIR_WATCH_MASK)?;
+            Ok((file_wd, dir_wd))
+        });
+        match watched {
+            Ok((file_wd, dir_wd)) => {
+                registry.add(file_wd, slot, None);
+                registry.add(dir_wd, slot, path.file_name().map(|name| name.to_owned()));
+                true
+            }
+            Err(e) => {
+                warn!("Failed to watch {}, polling instead: {}", slot.path, e);
+                // the watch of the file may have been added already
+                registry.remove(slot);
+                false
+            }
+        }
+    }
+}
+
+fn read_events(mut inotify: Inotify, shared: Weak<Shared>) {
+    let mut buffer = [0; 4096];
+    let mut pollfd = libc::pollfd {
+        fd: inotify.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    loop {
+        // not blocking in the read, so the thread and the inotify instance end with the watcher
+        let ready = unsafe { libc::poll(&mut pollfd, 1, INOTIFY_TIMEOUT.as_millis() as i32) };
+        if ready < 0 {
+            let e = std::io::Error::last_os_error();
+            if e.kind() == std::io::ErrorKind::Interrupted {
+                continue;
+            }
+            error!("Failed to wait for inotify events: {}", e);
+            return;
+        }
+        let shared = match shared.upgrade() {
+            Some(shared) => shared,
+            None => return,
+        };
+        if ready == 0 {
+            continue;
+        }
+        let events = match inotify.read_events(&mut buffer) {
+            Ok(events) => events,
+            Err(e) if e.kind() == std::io::ErrorKind::WouldBlock => continue,
+            Err(e) => {
+                error!("Failed to read inotify events: {}", e);
+                return;
+            }
+        };
+        let mut registry = shared.registry.lock().unwrap();
+        for event in events {
+            if event.mask.contains(EventMask::Q_OVERFLOW) {
+                // events were lost, so any file may have changed
+                registry
+                    .watched
+                    .values()
+                    .flatten()
+                    .for_each(|w| w.slot.notify());
+                continue;
+            }
+            if let Some(watched) = registry.watched.get(&event.wd) {
+                watched
+                    .iter()
+                    .filter(|w| w.name.is_none() || w.name.as_deref() == event.name)
+                    .for_each(|w| w.slot.notify());
+            }
+            // the file is gone, so is the watch
+            if event.mask.contains(EventMask::IGNORED) {
+                registry.watched.remove(&event.wd);
+            }
+        }
+    }
+}
+
+fn poll_files(shared: Weak<Shared>, interval: Duration) {
+    loop {
+        std::thread::sleep(interval);
+        let slots = match shared.upgrade() {
+            Some(shared) => shared.registry.lock().unwrap().polled.clone(),
+            None => return,
+        };
+        // files are checked without holding the registry, as this may block on network filesystems
+        for slot in slots {
+            let state = file_state(&slot.path);
+            let mut polled = slot.polled.lock().unwrap();
+            if *polled != state {
+                *polled = state;
+                drop(polled);
+                slot.notify();
+            }
+        }
+    }
+}
+
+/// A watched file, registered with the watcher until dropped.
+pub struct Watch {
+    slot: Arc<Slot>,
+    shared: Arc<Shared>,
+    seen: u64, // changes seen by the follower
+}
+
+impl Watch {
+    /// Ready if the file changed since the last call, otherwise the task is woken on the next change.
+    pub fn poll_change(&mut self, cx: &mut Context<'_>) -> Poll<()> {
+        // registered before checking, so a change in between wakes the task
+        *self.slot.waker.lock().unwrap() = Some(cx.waker().clone());
+        let changes = self.slot.changes.load(Ordering::SeqCst);
+        if changes == self.seen {
+            Poll::Pending
+        } else {
+            self.seen = changes;
+            Poll::Ready(())
+        }
+    }
+
+    /// A new watch of the same path, for the file found there now after the watched one was
+    /// rotated or deleted. Inotify watches the file, not the path.
+    pub fn rewatch(&self) -> Watch {
+        let watcher = FileWatcher {
+            shared: self.shared.clone(),
+        };
+        watcher.register(&self.slot.path, self.slot.dir)
+    }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        self.shared.registry.lock().unwrap().remove(&self.slot);
+    }
+}
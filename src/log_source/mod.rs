@@ -1,3 +1,8 @@
 pub use self::file_source::FileSource;
+pub use self::file_source::SourceFile;
+pub use self::file_tail::TailService;
+pub use self::file_watch::WatchConfig;
 
 mod file_source;
+mod file_tail;
+mod file_watch;
@@ -1,14 +1,46 @@
+use crate::cursor::Cursor;
 use crate::data;
 use crate::data::LogSource;
-use crate::data::LogStream;
+use crate::data::Marker;
+use crate::data::MarkerReason;
+use crate::data::Order;
+use crate::data::Origin;
+use crate::data::Repeat;
+use crate::data::SavedQuery;
+use crate::data::StreamEntry;
+use crate::delimited::DelimitedFormat;
+use crate::delimited::Delimiter;
+use crate::facets::Facets;
+use crate::facets::ValueCount;
+use crate::histogram::Counts;
+use crate::histogram::GroupBy;
+use crate::histogram::Histogram;
+use crate::log_dedupe::DedupeMode;
+use crate::log_limit::Limits;
+use crate::log_limit::LogLimit;
 use crate::log_merge::LogMerge;
+use crate::log_source::FileSource;
+use crate::log_source::SourceFile;
+use crate::otlp::OtlpFormat;
+use crate::proto;
+use crate::sse;
+use crate::template::Template;
+use crate::template::Templates;
+use crate::time_range::TimeRange;
+use crate::util;
+use crate::websocket::WsSession;
+use actix_http::ws;
+use actix_web::body::Body;
+use actix_web::body::SizedStream;
 use actix_web::error::ResponseError;
 use actix_web::http::header;
 use actix_web::web;
+use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use bytes::BufMut;
 use bytes::Bytes;
 use futures::stream::StreamExt;
+use std::collections::BTreeMap;
 use std::sync::Arc;
 
 use crate::data::ApplicationError;
@@ -16,6 +48,8 @@ use crate::data::LogQueryContext;
 use crate::logsource_svc::LogSourceService;
 use crate::state::ServerState;
 
+const DEFAULT_FACET_TOP: usize = 10;
+
 #[derive(Serialize, Deserialize, Debug)]
 struct ErrorResponse {
     message: String,
@@ -24,7 +58,9 @@ struct ErrorResponse {
 impl ResponseError for ApplicationError {
     fn error_response(&self) -> HttpResponse {
         match *self {
-            ApplicationError::SourceNotFound => HttpResponse::NotFound()
+            ApplicationError::SourceNotFound
+            | ApplicationError::QueryNotFound
+            | ApplicationError::FileNotFound => HttpResponse::NotFound()
                 .header(header::CONTENT_TYPE, "application/json")
                 .json(ErrorResponse {
                     message: self.to_string(),
@@ -34,6 +70,16 @@ impl ResponseError for ApplicationError {
                 .json(ErrorResponse {
                     message: self.to_string(),
                 }),
+            ApplicationError::CursorExpired => HttpResponse::Gone()
+                .header(header::CONTENT_TYPE, "application/json")
+                .json(ErrorResponse {
+                    message: self.to_string(),
+                }),
+            ApplicationError::InvalidQuery(_) => HttpResponse::BadRequest()
+                .header(header::CONTENT_TYPE, "application/json")
+                .json(ErrorResponse {
+                    message: self.to_string(),
+                }),
         }
     }
 }
@@ -54,13 +100,286 @@ pub struct LogSourceRepr {
     pub file_pattern: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub unit: Option<String>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub labels: BTreeMap<String, String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub template: Option<String>,
 }
 
 #[derive(Deserialize)]
 pub struct QueryParameters {
     from_ms: Option<i64>,
+    to_ms: Option<i64>,
+    from: Option<String>,  // epoch millis or RFC 3339 timestamp
+    until: Option<String>, // epoch millis or RFC 3339 timestamp
+    since: Option<String>, // duration before now, e.g. 15m
     loglevels: Option<String>,
     watch: Option<bool>,
+    cursor: Option<String>, // resume after the position of an earlier response
+    after: Option<usize>,
+    before: Option<usize>,
+    context: Option<usize>,
+    sample: Option<f64>,
+    max_lines: Option<u64>,
+    max_rate: Option<u64>,
+    dedupe: Option<String>, // collapse repeated lines of each source, not across sources
+    order: Option<String>,
+    fields: Option<String>, // keys of the json output or columns of csv and tsv
+    prefix: Option<String>, // origin shown before lines of the text output
+    template: Option<String>, // text output like `{ts:%H:%M:%S} [{level}] {message}`
+    download: Option<bool>, // send as attachment, named by sources and time range
+}
+
+#[derive(Deserialize)]
+pub struct SelectionParameters {
+    sources: Option<String>, // comma separated id patterns, `*` and `?` as wildcards
+    labels: Option<String>,  // comma separated key=value pairs, all need to match
+}
+
+#[derive(Deserialize)]
+pub struct HistogramParameters {
+    bucket: Option<String>,
+    group_by: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+struct BucketRepr<'a> {
+    start_ms: u128,
+    #[serde(flatten)]
+    counts: &'a Counts,
+}
+
+#[derive(Serialize, Debug)]
+struct HistogramRepr<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    bucket_ms: Option<u128>,
+    total: &'a Counts,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    buckets: Option<Vec<BucketRepr<'a>>>,
+}
+
+impl<'a> From<&'a Histogram> for HistogramRepr<'a> {
+    fn from(histogram: &'a Histogram) -> Self {
+        HistogramRepr {
+            bucket_ms: histogram.bucket_ms(),
+            total: histogram.total(),
+            buckets: histogram.bucket_ms().map(|_| {
+                histogram
+                    .buckets()
+                    .map(|(start_ms, counts)| BucketRepr {
+                        start_ms: *start_ms,
+                        counts,
+                    })
+                    .collect()
+            }),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+pub struct FacetParameters {
+    field: String,
+    top: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+struct FacetRepr<'a> {
+    field: &'a str,
+    approximate: bool,
+    missing: u64,
+    values: Vec<ValueCount>,
+}
+
+#[derive(Serialize, Debug)]
+struct FacetsRepr<'a> {
+    total: u64,
+    facets: Vec<FacetRepr<'a>>,
+}
+
+impl<'a> From<&'a Facets> for FacetsRepr<'a> {
+    fn from(facets: &'a Facets) -> Self {
+        FacetsRepr {
+            total: facets.total(),
+            facets: facets
+                .fields()
+                .map(|(field, values)| FacetRepr {
+                    field,
+                    approximate: values.approximate(),
+                    missing: values.missing(),
+                    values: values.top(facets.top()),
+                })
+                .collect(),
+        }
+    }
+}
+
+/// Keys of the json output, as selected by the `fields` parameter.
+/// Further names select captures of the line pattern, cursor and annotations are always included.
+#[derive(Debug)]
+struct Projection {
+    timestamp: bool,
+    loglevel: bool,
+    message: bool,
+    raw: bool,
+    source: bool,
+    file: bool,
+    line: bool,
+    captures: Option<Vec<String>>, // None for all captures
+}
+
+impl Default for Projection {
+    fn default() -> Self {
+        Projection {
+            timestamp: true,
+            loglevel: true,
+            message: true,
+            raw: false,
+            source: false,
+            file: false,
+            line: false,
+            captures: None,
+        }
+    }
+}
+
+impl Projection {
+    fn parse(fields: &str) -> Result<Projection, ApplicationError> {
+        let mut projection = Projection {
+            timestamp: false,
+            loglevel: false,
+            message: false,
+            raw: false,
+            source: false,
+            file: false,
+            line: false,
+            captures: Some(Vec::new()),
+        };
+        for field in fields.split(',').map(str::trim) {
+            match field {
+                "timestamp" => projection.timestamp = true,
+                "loglevel" => projection.loglevel = true,
+                "message" => projection.message = true,
+                "raw" => projection.raw = true,
+                "source" => projection.source = true,
+                "file" => projection.file = true,
+                "line" => projection.line = true,
+                "" => {
+                    return Err(ApplicationError::InvalidQuery(format!(
+                        "Invalid fields: {}",
+                        fields
+                    )))
+                }
+                capture => {
+                    if let Some(captures) = projection.captures.as_mut() {
+                        captures.push(capture.to_string());
+                    }
+                }
+            }
+        }
+        Ok(projection)
+    }
+
+    /// Fails for selected names, which are none of the keys and captures of the line patterns of the sources.
+    fn check_captures(&self, capture_names: &[String]) -> Result<(), ApplicationError> {
+        let captures = self.captures.iter().flatten();
+        match captures.into_iter().find(|c| !capture_names.contains(c)) {
+            Some(unknown) => Err(ApplicationError::InvalidQuery(format!(
+                "Unknown field: {}",
+                unknown
+            ))),
+            None => Ok(()),
+        }
+    }
+
+    fn apply<'a>(&self, entry: &'a StreamEntry, timestamp: u128, cursor: String) -> JsonEntry<'a> {
+        let parsed_line = &entry.parsed_line;
+        let origin = entry.origin.as_ref();
+        JsonEntry {
+            timestamp: if self.timestamp {
+                Some(timestamp as u64)
+            } else {
+                None
+            },
+            loglevel: if self.loglevel {
+                Some(parsed_line.loglevel.as_deref())
+            } else {
+                None
+            },
+            message: if self.message {
+                Some(&parsed_line.message)
+            } else {
+                None
+            },
+            fields: parsed_line
+                .fields
+                .iter()
+                .filter(|(name, _)| match &self.captures {
+                    Some(captures) => captures.contains(name),
+                    None => true,
+                })
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect(),
+            raw: if self.raw { Some(&entry.line) } else { None },
+            source: origin.filter(|_| self.source).map(|o| o.source.as_str()),
+            file: origin.filter(|_| self.file).map(|o| o.file.as_str()),
+            line: origin.filter(|_| self.line).and_then(|o| o.line),
+            cursor,
+            context: entry.context,
+            marker: entry.marker.as_ref(),
+            repeat: entry.repeat.as_ref(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct JsonEntry<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timestamp: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    loglevel: Option<Option<&'a str>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<&'a str>,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    fields: BTreeMap<&'a str, &'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    raw: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    file: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<u64>,
+    cursor: String,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    context: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    marker: Option<&'a Marker>,
+    #[serde(flatten)]
+    repeat: Option<&'a Repeat>,
+}
+
+// null values of the json output are left out
+impl<'a> From<&JsonEntry<'a>> for proto::Entry {
+    fn from(entry: &JsonEntry<'a>) -> Self {
+        proto::Entry {
+            timestamp: entry.timestamp,
+            loglevel: entry.loglevel.flatten().map(str::to_string),
+            message: entry.message.map(str::to_string),
+            fields: entry
+                .fields
+                .iter()
+                .map(|(name, value)| (name.to_string(), value.to_string()))
+                .collect(),
+            raw: entry.raw.map(str::to_string),
+            source: entry.source.map(str::to_string),
+            file: entry.file.map(str::to_string),
+            line: entry.line,
+            cursor: entry.cursor.clone(),
+            context: entry.context,
+            marker: entry.marker.map(proto::Marker::from),
+            repeat: entry.repeat.map(proto::Repeat::from),
+        }
+    }
 }
 
 impl From<&data::LogSource> for LogSourceRepr {
@@ -70,23 +389,31 @@ impl From<&data::LogSource> for LogSourceRepr {
                 id,
                 file_pattern,
                 line_pattern,
+                labels,
+                template,
             } => LogSourceRepr {
                 src_type: LogSourceType::File,
                 id: id.to_string(),
                 line_pattern: Some(line_pattern.raw.clone()),
                 file_pattern: Some(file_pattern.to_string()),
                 unit: None,
+                labels: labels.clone(),
+                template: template.as_ref().map(|t| t.as_str().to_string()),
             },
             LogSource::Journal {
                 id,
                 unit,
                 line_pattern,
+                labels,
+                template,
             } => LogSourceRepr {
                 src_type: LogSourceType::Journal,
                 id: id.to_string(),
                 line_pattern: Some(line_pattern.raw.clone()),
                 file_pattern: None,
                 unit: Some(unit.to_string()),
+                labels: labels.clone(),
+                template: template.as_ref().map(|t| t.as_str().to_string()),
             },
         }
     }
@@ -101,99 +428,834 @@ pub fn get_sources(state: web::Data<ServerState>) -> HttpResponse {
     HttpResponse::Ok().json(dto)
 }
 
-fn logfilter_from_query(parameters: &QueryParameters) -> LogQueryContext {
-    LogQueryContext {
-        from_ms: parameters.from_ms.map(|dt| dt as u128).unwrap_or(0),
+/// The requested time range, in the parameters shared by all endpoints taking a range.
+fn time_range_from_query(parameters: &QueryParameters) -> TimeRange {
+    TimeRange {
+        from_ms: parameters.from_ms,
+        to_ms: parameters.to_ms,
+        from: parameters.from.clone(),
+        until: parameters.until.clone(),
+        since: parameters.since.clone(),
+    }
+}
+
+fn logfilter_from_query(parameters: &QueryParameters) -> Result<LogQueryContext, ApplicationError> {
+    let after = match &parameters.cursor {
+        Some(cursor) => Some(Cursor::decode(cursor)?),
+        None => None,
+    };
+    let dedupe = match &parameters.dedupe {
+        Some(mode) => Some(DedupeMode::parse(mode)?),
+        None => None,
+    };
+    let (from_ms, to_ms) = time_range_from_query(parameters).resolve()?;
+    let order = match &parameters.order {
+        Some(order) => Order::parse(order)?,
+        None => Order::Asc,
+    };
+    if order == Order::Desc && parameters.watch == Some(true) {
+        return Err(ApplicationError::InvalidQuery(
+            "Newest-first order cannot be watched".to_string(),
+        ));
+    }
+    Ok(LogQueryContext {
+        from_ms,
+        to_ms,
         loglevels: parameters
             .loglevels
             .clone()
             .map(|s| s.split(",").map(|s| s.to_string()).collect()),
         watch: parameters.watch,
+        after,
+        before_lines: parameters.before.or(parameters.context).unwrap_or(0),
+        after_lines: parameters.after.or(parameters.context).unwrap_or(0),
+        dedupe,
+        order,
+    })
+}
+
+/// Filter for aggregations, which need a finite stream of the matching entries only.
+fn aggregation_filter_from_query(
+    parameters: &QueryParameters,
+) -> Result<LogQueryContext, ApplicationError> {
+    logfilter_from_query(parameters).map(|logfilter| LogQueryContext {
+        watch: None,
+        before_lines: 0,
+        after_lines: 0,
+        dedupe: None,
+        order: Order::Asc,
+        ..logfilter
+    })
+}
+
+fn limits_from_query(parameters: &QueryParameters) -> Result<Limits, ApplicationError> {
+    if let Some(sample) = parameters.sample {
+        if !(sample > 0.0 && sample <= 1.0) {
+            return Err(ApplicationError::InvalidQuery(format!(
+                "Sample rate must be within (0, 1]: {}",
+                sample
+            )));
+        }
     }
+    if parameters.max_rate == Some(0) {
+        return Err(ApplicationError::InvalidQuery(
+            "Maximum rate must be at least 1".to_string(),
+        ));
+    }
+    Ok(Limits {
+        sample: parameters.sample,
+        max_rate: parameters.max_rate,
+        max_lines: parameters.max_lines,
+    })
 }
 
-fn get_source_content(
-    id: web::Path<String>,
-    filter: web::Query<QueryParameters>,
-    state: web::Data<ServerState>,
-    as_json: bool,
-) -> HttpResponse {
-    debug!("Content for source {} requested", id);
+fn histogram_from_query(parameters: &HistogramParameters) -> Result<Histogram, ApplicationError> {
+    let bucket_ms = match &parameters.bucket {
+        Some(bucket) => match util::parse_duration_ms(bucket) {
+            Some(ms) if ms > 0 => Some(ms),
+            _ => {
+                return Err(ApplicationError::InvalidQuery(format!(
+                    "Invalid bucket size: {}",
+                    bucket
+                )))
+            }
+        },
+        None => None,
+    };
+    let group_by = match &parameters.group_by {
+        Some(field) => Some(GroupBy::parse(field)?),
+        None => None,
+    };
+    Ok(Histogram::new(bucket_ms, group_by))
+}
 
-    let ids_string = id.to_string();
-    let ids: Vec<&str> = ids_string.split(",").collect();
+/// Text prefix of a line, naming its origin like `[source file:line] `.
+fn text_prefix(parts: &[String], origin: Option<&Origin>) -> String {
+    let origin = match origin {
+        Some(origin) if !parts.is_empty() => origin,
+        _ => return String::new(),
+    };
+    let mut location = Vec::new();
+    for part in parts {
+        match part.as_str() {
+            "source" => location.push(origin.source.to_string()),
+            "file" => location.push(origin.file.to_string()),
+            _ => {
+                if let Some(line) = origin.line {
+                    location.push(line.to_string())
+                }
+            }
+        }
+    }
+    if location.is_empty() {
+        String::new()
+    } else {
+        format!("[{}] ", location.join(":"))
+    }
+}
+
+fn prefix_from_query(parameters: &QueryParameters) -> Result<Vec<String>, ApplicationError> {
+    match &parameters.prefix {
+        Some(prefix) => prefix
+            .split(',')
+            .map(|part| match part {
+                "source" | "file" | "line" => Ok(part.to_string()),
+                p => Err(ApplicationError::InvalidQuery(format!(
+                    "Unsupported prefix: {}",
+                    p
+                ))),
+            })
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Templates of the text output, the requested one or those of the sources with the configured one as default.
+fn templates_from_query(
+    parameters: &QueryParameters,
+    ids: &[&str],
+    state: &ServerState,
+) -> Result<Templates, ApplicationError> {
+    if let Some(template) = &parameters.template {
+        return Ok(Templates {
+            sources: BTreeMap::new(),
+            default: Some(Template::parse(template)?),
+        });
+    }
+    let mut sources = BTreeMap::new();
+    for id in ids {
+        let template = match state.lookup_source(id) {
+            Some(LogSource::File { template, .. }) => template,
+            Some(LogSource::Journal { template, .. }) => template,
+            None => None,
+        };
+        if let Some(template) = template {
+            sources.insert(id.to_string(), template);
+        }
+    }
+    Ok(Templates {
+        sources,
+        default: state.get_template(),
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ContentFormat {
+    Text,
+    Json,     // a single json array
+    NdJson,   // newline delimited json objects
+    Events,   // server-sent events of json objects
+    Csv,      // comma separated values with a header row
+    Tsv,      // tab separated values with a header row
+    MsgPack,  // concatenated msgpack maps with the keys of the json objects
+    Protobuf, // length delimited frames as in proto/tentacle.proto
+    Otlp,     // newline delimited OpenTelemetry LogsData
+}
+
+impl ContentFormat {
+    fn content_type(self) -> &'static str {
+        match self {
+            ContentFormat::Text => "text/plain",
+            ContentFormat::Json => "application/json",
+            ContentFormat::NdJson => "application/x-ndjson",
+            ContentFormat::Events => "text/event-stream",
+            ContentFormat::Csv => "text/csv",
+            ContentFormat::Tsv => "text/tab-separated-values",
+            ContentFormat::MsgPack => "application/msgpack",
+            ContentFormat::Protobuf => "application/x-protobuf",
+            ContentFormat::Otlp => "application/x-otlp+json",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            ContentFormat::Text => "log",
+            ContentFormat::Json => "json",
+            ContentFormat::NdJson => "ndjson",
+            ContentFormat::Events => "events",
+            ContentFormat::Csv => "csv",
+            ContentFormat::Tsv => "tsv",
+            ContentFormat::MsgPack => "msgpack",
+            ContentFormat::Protobuf => "pb",
+            ContentFormat::Otlp => "otlp.jsonl",
+        }
+    }
+}
 
-    let stream_result: Result<Vec<LogStream>, ApplicationError> = ids
+/// Resource attributes of the otlp output, with the labels of the sources.
+fn otlp_from_query(ids: &[&str], state: &ServerState) -> OtlpFormat {
+    let host = hostname::get()
+        .map(|host| host.to_string_lossy().into_owned())
+        .unwrap_or_default();
+    let labels = ids
         .iter()
-        .map(|id| {
-            LogSourceService::create_content_stream(
-                String::from(*id),
-                state.get_ref().clone(),
-                &Arc::new(logfilter_from_query(&filter)),
-            )
+        .filter_map(|id| state.lookup_source(id))
+        .map(|source| match source {
+            LogSource::File { id, labels, .. } => (id, labels),
+            LogSource::Journal { id, labels, .. } => (id, labels),
         })
-        .fold(Ok(Vec::<LogStream>::new()), |ret, res| match res {
-            Ok(stream) => {
-                if let Ok(mut vec) = ret {
-                    vec.push(stream);
-                    Ok(vec)
-                } else {
-                    ret
-                }
+        .collect();
+    OtlpFormat::new(host, labels)
+}
+
+/// Names of the captures of the line patterns of the sources, in the order of the sources.
+fn capture_names(ids: &[&str], state: &ServerState) -> Vec<String> {
+    let mut names: Vec<String> = Vec::new();
+    for id in ids {
+        let line_pattern = match state.lookup_source(id) {
+            Some(LogSource::File { line_pattern, .. }) => line_pattern,
+            Some(LogSource::Journal { line_pattern, .. }) => line_pattern,
+            None => continue,
+        };
+        for name in util::capture_names(&line_pattern.raw) {
+            if !names.contains(&name) {
+                names.push(name);
             }
-            _err => ret,
-        });
+        }
+    }
+    names
+}
+
+/// Keys of the json output as selected by the `fields` parameter, checked against the sources.
+fn projection_from_query(
+    parameters: &QueryParameters,
+    ids: &[&str],
+    state: &ServerState,
+) -> Result<Projection, ApplicationError> {
+    match &parameters.fields {
+        Some(fields) => {
+            let projection = Projection::parse(fields)?;
+            projection.check_captures(&capture_names(ids, state))?;
+            Ok(projection)
+        }
+        // merged entries name their source
+        None => Ok(Projection {
+            source: ids.len() > 1,
+            ..Projection::default()
+        }),
+    }
+}
+
+/// Columns of csv and tsv output, either as given by the `fields` parameter or the timestamp,
+/// loglevel, message and further captures of the line patterns of the sources.
+fn columns_from_query(
+    parameters: &QueryParameters,
+    ids: &[&str],
+    state: &ServerState,
+) -> Result<Vec<String>, ApplicationError> {
+    if let Some(fields) = &parameters.fields {
+        projection_from_query(parameters, ids, state)?;
+        return Ok(fields.split(',').map(|f| f.trim().to_string()).collect());
+    }
+    let mut columns: Vec<String> = vec!["timestamp".into(), "loglevel".into(), "message".into()];
+    for name in capture_names(ids, state) {
+        if !columns.contains(&name) {
+            columns.push(name);
+        }
+    }
+    // merged entries name their source
+    if ids.len() > 1 {
+        columns.push("source".into());
+    }
+    Ok(columns)
+}
+
+/// Attachment name like `web+db_20190101T090000Z_20190101T100000Z.csv`, with the bounds of the time range if given.
+fn download_filename(ids: &[&str], logfilter: &LogQueryContext, format: ContentFormat) -> String {
+    let sources: String = ids
+        .join("+")
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "+-.".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect();
+    let mut parts = vec![if sources.is_empty() {
+        "logs".to_string()
+    } else {
+        sources
+    }];
+    if logfilter.from_ms > 0 {
+        parts.push(util::format_timestamp_ms(
+            logfilter.from_ms,
+            "%Y%m%dT%H%M%SZ",
+        ));
+    }
+    if let Some(to_ms) = logfilter.to_ms {
+        parts.push(util::format_timestamp_ms(to_ms, "%Y%m%dT%H%M%SZ"));
+    }
+    format!("{}.{}", parts.join("_"), format.extension())
+}
+
+fn get_content(
+    ids: &[&str],
+    filter: &QueryParameters,
+    state: web::Data<ServerState>,
+    format: ContentFormat,
+) -> HttpResponse {
+    let logfilter = match logfilter_from_query(filter) {
+        Ok(logfilter) => Arc::new(logfilter),
+        Err(e) => return e.error_response(),
+    };
+    if format == ContentFormat::Json && logfilter.watch == Some(true) {
+        let e = ApplicationError::InvalidQuery(
+            "A json array cannot be watched, use application/x-ndjson instead".to_string(),
+        );
+        return e.error_response();
+    }
+    let limits = match limits_from_query(filter) {
+        Ok(limits) => limits,
+        Err(e) => return e.error_response(),
+    };
+    let projection = match projection_from_query(filter, ids, &state) {
+        Ok(projection) => projection,
+        Err(e) => return e.error_response(),
+    };
+    let prefix = match prefix_from_query(filter) {
+        Ok(prefix) => prefix,
+        Err(e) => return e.error_response(),
+    };
+    let templates = match format {
+        ContentFormat::Text => match templates_from_query(filter, ids, &state) {
+            Ok(templates) => templates,
+            Err(e) => return e.error_response(),
+        },
+        _ => Templates::default(),
+    };
+    let delimited = match format {
+        ContentFormat::Csv | ContentFormat::Tsv => {
+            let delimiter = if format == ContentFormat::Csv {
+                Delimiter::Comma
+            } else {
+                Delimiter::Tab
+            };
+            match columns_from_query(filter, ids, &state) {
+                Ok(columns) => Some(DelimitedFormat::new(columns, delimiter)),
+                Err(e) => return e.error_response(),
+            }
+        }
+        _ => None,
+    };
+    let otlp = match format {
+        ContentFormat::Otlp => Some(otlp_from_query(ids, &state)),
+        _ => None,
+    };
+    let header = delimited.as_ref().map(DelimitedFormat::header);
+    let filename = match filter.download {
+        Some(true) => Some(download_filename(ids, &logfilter, format)),
+        _ => None,
+    };
+    // the cursor starts with the positions of the resumed request, so sources without new lines keep theirs
+    let mut cursor = logfilter.after.clone().unwrap_or_default();
+
+    let stream_result =
+        LogSourceService::create_merged_stream(ids, state.get_ref().clone(), &logfilter);
 
     match stream_result {
-        Ok(stream) => {
+        Ok(merged) => {
             let mut last_ts = 0;
-            let merged = LogMerge::new(stream);
-
-            let mapped_stream = merged.map(move |stream_entry| {
-                let mut parsed_line = stream_entry.parsed_line;
-                let line = stream_entry.line;
-                if as_json {
-                    if parsed_line.timestamp == 0 {
-                        parsed_line.timestamp = last_ts;
-                    } else {
-                        last_ts = parsed_line.timestamp;
+            let mut first = true;
+            // limits apply to the filtered and merged entries
+            let limited = LogLimit::new(merged, limits);
+            let mapped_stream = limited.map(move |stream_entry| {
+                if let Some(source_cursor) = &stream_entry.cursor {
+                    cursor.advance(source_cursor);
+                }
+                // lines without timestamp take the one of the preceding line
+                let timestamp = if stream_entry.timestamp() == 0 {
+                    last_ts
+                } else {
+                    last_ts = stream_entry.timestamp();
+                    last_ts
+                };
+                if format == ContentFormat::Text {
+                    let mut vec = text_prefix(&prefix, stream_entry.origin.as_ref()).into_bytes();
+                    match templates.select(&stream_entry) {
+                        Some(template) => {
+                            vec.put_slice(template.render(&stream_entry, timestamp).as_bytes())
+                        }
+                        None => vec.put_slice(stream_entry.line.as_bytes()),
                     }
-
-                    match serde_json::to_vec(&parsed_line) {
-                        Ok(mut vec) => {
+                    if let Some(repeat) = &stream_entry.repeat {
+                        vec.put_slice(format!(" (repeated {} times)", repeat.count).as_bytes());
+                    }
+                    vec.put_u8('\n' as u8);
+                    return Ok(Bytes::from(vec));
+                }
+                if let Some(delimited) = &delimited {
+                    return Ok(delimited.entry(&stream_entry, timestamp));
+                }
+                let encoded_cursor = cursor.encode();
+                if let Some(otlp) = &otlp {
+                    return otlp
+                        .entry(&stream_entry, timestamp, &encoded_cursor)
+                        .map_err(|e| {
+                            error!("Failed to convert stream entry to otlp: {}", e);
+                            ApplicationError::FailedToReadSource
+                        });
+                }
+                let entry = projection.apply(&stream_entry, timestamp, encoded_cursor.clone());
+                match format {
+                    ContentFormat::MsgPack => {
+                        return rmp_serde::to_vec_named(&entry)
+                            .map(Bytes::from)
+                            .map_err(|e| {
+                                error!("Failed to convert stream entry to msgpack: {}", e);
+                                ApplicationError::FailedToReadSource
+                            })
+                    }
+                    ContentFormat::Protobuf => {
+                        let entry = Box::new(proto::Entry::from(&entry));
+                        return Ok(proto::frame(proto::FrameKind::Entry(entry)));
+                    }
+                    _ => (),
+                }
+                let mut vec = Vec::new();
+                if format == ContentFormat::Json && !first {
+                    vec.put_slice(b",\n");
+                }
+                first = false;
+                match serde_json::to_writer(&mut vec, &entry) {
+                    Ok(()) => match format {
+                        ContentFormat::NdJson => {
                             vec.put_u8('\n' as u8);
                             Ok(Bytes::from(vec))
                         }
-                        Err(e) => {
-                            error!("Failed to convert stream entry to json: {}", e);
-                            Err(ApplicationError::FailedToReadSource) // TODO json error //Bytes::new()
+                        ContentFormat::Events => {
+                            let event_type = stream_entry.marker.as_ref().map(|_| "marker");
+                            let data = String::from_utf8_lossy(&vec);
+                            Ok(sse::event(Some(&encoded_cursor), event_type, &data))
                         }
+                        _ => Ok(Bytes::from(vec)),
+                    },
+                    Err(e) => {
+                        error!("Failed to convert stream entry to json: {}", e);
+                        Err(ApplicationError::FailedToReadSource) // TODO json error //Bytes::new()
                     }
-                } else {
-                    let mut vec = line.into_bytes();
-                    vec.put_u8('\n' as u8);
-                    Ok(Bytes::from(vec))
                 }
             });
 
-            HttpResponse::Ok()
-                .content_type(if as_json {
-                    "application/json"
-                } else {
-                    "text/plain"
+            let mut response = HttpResponse::Ok();
+            response.content_type(format.content_type());
+            if let Some(filename) = filename {
+                response.header(
+                    header::CONTENT_DISPOSITION,
+                    format!("attachment; filename=\"{}\"", filename),
+                );
+            }
+            match format {
+                ContentFormat::Json => {
+                    let open = futures::stream::once(async { Ok(Bytes::from_static(b"[")) });
+                    let close = futures::stream::once(async { Ok(Bytes::from_static(b"]\n")) });
+                    response.streaming(open.chain(mapped_stream).chain(close))
+                }
+                ContentFormat::Events => {
+                    response
+                        .header(header::CACHE_CONTROL, "no-cache")
+                        // disables response buffering of nginx
+                        .header("X-Accel-Buffering", "no")
+                        .streaming(sse::Heartbeat::new(mapped_stream, sse::HEARTBEAT_INTERVAL))
+                }
+                ContentFormat::Csv | ContentFormat::Tsv => {
+                    let header = futures::stream::iter(header.map(Ok));
+                    response.streaming(header.chain(mapped_stream))
+                }
+                // a trailer tells a complete stream from a broken connection
+                ContentFormat::Protobuf => response.streaming(proto::Trailed::new(mapped_stream)),
+                _ => response.streaming(mapped_stream),
+            }
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+#[derive(Serialize, Debug)]
+struct SourceFileRepr<'a> {
+    name: &'a str,
+    size: u64,
+    modified_ms: u128,
+    rotation: i32,
+}
+
+#[derive(Deserialize)]
+pub struct DownloadParameters {
+    decompress: Option<bool>, // decompress gzip files on the fly
+}
+
+/// Files of a source, empty for sources without files.
+fn source_files(id: &str, state: &ServerState) -> Result<Vec<SourceFile>, ApplicationError> {
+    match state.lookup_source(id) {
+        Some(LogSource::File { file_pattern, .. }) => FileSource::list_files(&file_pattern),
+        Some(LogSource::Journal { .. }) => Ok(Vec::new()),
+        None => Err(ApplicationError::SourceNotFound),
+    }
+}
+
+pub fn get_source_files(id: web::Path<String>, state: web::Data<ServerState>) -> HttpResponse {
+    match source_files(&id, &state) {
+        Ok(files) => {
+            let dto: Vec<SourceFileRepr> = files
+                .iter()
+                .map(|file| SourceFileRepr {
+                    name: &file.name,
+                    size: file.size,
+                    modified_ms: file.modified_ms,
+                    rotation: file.rotation,
                 })
-                .streaming(mapped_stream)
+                .collect();
+            HttpResponse::Ok().json(dto)
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+/// Entity tag of a file, changing whenever the file is replaced or written to.
+fn file_etag(file: &SourceFile, decompress: bool) -> String {
+    format!(
+        "\"{:x}-{:x}-{:x}{}\"",
+        file.identity.1,
+        file.size,
+        file.modified_ms,
+        if decompress { "-d" } else { "" }
+    )
+}
+
+fn etag_matches(header: Option<&str>, etag: &str) -> bool {
+    header
+        .map(|tags| {
+            tags.split(',')
+                .map(|tag| tag.trim().trim_start_matches("W/"))
+                .any(|tag| tag == "*" || tag == etag)
+        })
+        .unwrap_or(false)
+}
+
+#[derive(Debug, PartialEq)]
+enum ByteRange {
+    Full,
+    Partial(u64, u64), // first and last byte
+    Unsatisfiable,
+}
+
+/// Parse a `Range` header for a single byte range, other and invalid ranges are ignored.
+fn parse_range(range: &str, size: u64) -> ByteRange {
+    let spec = match range.trim().strip_prefix("bytes=") {
+        Some(spec) if !spec.contains(',') => spec.trim(),
+        _ => return ByteRange::Full,
+    };
+    let mut parts = spec.splitn(2, '-');
+    let (first, last) = match (parts.next(), parts.next()) {
+        (Some(first), Some(last)) => (first.trim(), last.trim()),
+        _ => return ByteRange::Full,
+    };
+    if first.is_empty() {
+        // the last bytes of the file
+        return match last.parse::<u64>() {
+            Ok(0) => ByteRange::Unsatisfiable,
+            Ok(_) if size == 0 => ByteRange::Unsatisfiable,
+            Ok(suffix) => ByteRange::Partial(size.saturating_sub(suffix), size - 1),
+            Err(_) => ByteRange::Full,
+        };
+    }
+    let first = match first.parse::<u64>() {
+        Ok(first) => first,
+        Err(_) => return ByteRange::Full,
+    };
+    let last = if last.is_empty() {
+        size.saturating_sub(1)
+    } else {
+        match last.parse::<u64>() {
+            Ok(last) if last >= first => last.min(size.saturating_sub(1)),
+            _ => return ByteRange::Full,
+        }
+    };
+    if first >= size {
+        ByteRange::Unsatisfiable
+    } else {
+        ByteRange::Partial(first, last)
+    }
+}
+
+/// Download a file of a source, as stored or decompressed with `decompress=true`.
+/// Stored bytes can be requested in parts by a `Range` header.
+pub fn get_source_file(
+    req: HttpRequest,
+    path: web::Path<(String, String)>,
+    parameters: web::Query<DownloadParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    let (id, name) = path.into_inner();
+    debug!("File {} of source {} requested", name, id);
+
+    let file = match state.lookup_source(&id) {
+        Some(LogSource::File { file_pattern, .. }) => FileSource::find_file(&file_pattern, &name),
+        Some(LogSource::Journal { .. }) => Err(ApplicationError::FileNotFound),
+        None => Err(ApplicationError::SourceNotFound),
+    };
+    let file = match file {
+        Ok(file) => file,
+        Err(e) => return e.error_response(),
+    };
+    let decompress = parameters.decompress == Some(true) && file.is_gzip();
+    let etag = file_etag(&file, decompress);
+    let header_value = |name| req.headers().get(name).and_then(|v| v.to_str().ok());
+
+    if etag_matches(header_value(header::IF_NONE_MATCH), &etag) {
+        return HttpResponse::NotModified()
+            .header(header::ETAG, etag)
+            .finish();
+    }
+    // a range applies to the stored bytes, as long as they did not change since the client saw them
+    let range = match header_value(header::RANGE) {
+        Some(range) if !decompress => match header_value(header::IF_RANGE) {
+            Some(if_range) if if_range != etag => ByteRange::Full,
+            _ => parse_range(range, file.size),
+        },
+        _ => ByteRange::Full,
+    };
+    if range == ByteRange::Unsatisfiable {
+        return HttpResponse::RangeNotSatisfiable()
+            .header(header::CONTENT_RANGE, format!("bytes */{}", file.size))
+            .finish();
+    }
+
+    let filename = if decompress {
+        name.trim_end_matches(".gz")
+    } else {
+        &name
+    };
+    let content_type = if file.is_gzip() && !decompress {
+        "application/gzip"
+    } else {
+        "text/plain"
+    };
+    let byte_range = match range {
+        ByteRange::Partial(first, last) => Some((first, last)),
+        _ => None,
+    };
+    let chunks = match file.open(byte_range, decompress) {
+        Ok(chunks) => chunks.map(|chunk| chunk.map_err(actix_web::Error::from)),
+        Err(e) => {
+            error!("Failed to open {}: {}", file.path, e);
+            return ApplicationError::FailedToReadSource.error_response();
+        }
+    };
+
+    let mut response = match range {
+        ByteRange::Partial(..) => HttpResponse::PartialContent(),
+        _ => HttpResponse::Ok(),
+    };
+    response
+        .content_type(content_type)
+        .header(header::ETAG, etag)
+        .header(
+            header::CONTENT_DISPOSITION,
+            format!("attachment; filename=\"{}\"", filename),
+        );
+    if decompress {
+        // the decompressed size is not known in advance
+        return response
+            .header(header::ACCEPT_RANGES, "none")
+            .streaming(chunks);
+    }
+    response.header(header::ACCEPT_RANGES, "bytes");
+    let length = match range {
+        ByteRange::Partial(first, last) => {
+            response.header(
+                header::CONTENT_RANGE,
+                format!("bytes {}-{}/{}", first, last, file.size),
+            );
+            last + 1 - first
+        }
+        _ => file.size,
+    };
+    response.body(Body::from_message(SizedStream::new(length, chunks)))
+}
+
+/// Count the matching entries of the given sources, in total or per time bucket.
+/// The entries are streamed through the histogram, only the counters are kept.
+pub async fn get_source_histogram(
+    id: web::Path<String>,
+    filter: web::Query<QueryParameters>,
+    parameters: web::Query<HistogramParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    debug!("Histogram for source {} requested", id);
+
+    let ids_string = id.to_string();
+    let ids: Vec<&str> = ids_string.split(",").collect();
+
+    let histogram = match histogram_from_query(&parameters) {
+        Ok(histogram) => histogram,
+        Err(e) => return e.error_response(),
+    };
+    let logfilter = match aggregation_filter_from_query(&filter) {
+        Ok(logfilter) => Arc::new(logfilter),
+        Err(e) => return e.error_response(),
+    };
+
+    match LogSourceService::create_merged_stream(&ids, state.get_ref().clone(), &logfilter) {
+        Ok(merged) => {
+            let mut histogram = histogram;
+            let aggregated = aggregate(merged, |stream_entry| {
+                histogram.add(&stream_entry.parsed_line)
+            });
+            match aggregated.await {
+                Ok(()) => HttpResponse::Ok().json(HistogramRepr::from(&histogram)),
+                Err(e) => e.error_response(),
+            }
         }
         Err(e) => e.error_response(),
     }
 }
 
+/// Add the entries of the merged sources to an aggregation.
+/// Fails if a source fails while reading, instead of aggregating only a part of its entries.
+async fn aggregate<F>(mut merged: LogMerge, mut add: F) -> Result<(), ApplicationError>
+where
+    F: FnMut(&StreamEntry) -> Result<(), ApplicationError>,
+{
+    while let Some(stream_entry) = merged.next().await {
+        if let Some(Marker {
+            reason: MarkerReason::Failed,
+            ..
+        }) = stream_entry.marker
+        {
+            return Err(ApplicationError::FailedToReadSource);
+        }
+        add(&stream_entry)?;
+    }
+    Ok(())
+}
+
+/// Approximate the most frequent values of the requested fields for the given sources.
+pub async fn get_source_facets(
+    id: web::Path<String>,
+    filter: web::Query<QueryParameters>,
+    parameters: web::Query<FacetParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    debug!("Facets for source {} requested", id);
+
+    let ids_string = id.to_string();
+    let ids: Vec<&str> = ids_string.split(",").collect();
+
+    let fields: Vec<String> = parameters
+        .field
+        .split(',')
+        .map(str::trim)
+        .filter(|f| !f.is_empty())
+        .map(|f| f.to_string())
+        .collect();
+    if fields.is_empty() {
+        return ApplicationError::InvalidQuery("No facet field given".to_string()).error_response();
+    }
+    let facets = match Facets::new(&fields, parameters.top.unwrap_or(DEFAULT_FACET_TOP)) {
+        Ok(facets) => facets,
+        Err(e) => return e.error_response(),
+    };
+    let logfilter = match aggregation_filter_from_query(&filter) {
+        Ok(logfilter) => Arc::new(logfilter),
+        Err(e) => return e.error_response(),
+    };
+
+    match LogSourceService::create_merged_stream(&ids, state.get_ref().clone(), &logfilter) {
+        Ok(merged) => {
+            let mut facets = facets;
+            let aggregated = aggregate(merged, |stream_entry| {
+                facets.add(&stream_entry.parsed_line);
+                Ok(())
+            });
+            match aggregated.await {
+                Ok(()) => HttpResponse::Ok().json(FacetsRepr::from(&facets)),
+                Err(e) => e.error_response(),
+            }
+        }
+        Err(e) => e.error_response(),
+    }
+}
+
+fn get_source_content(
+    id: web::Path<String>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+    format: ContentFormat,
+) -> HttpResponse {
+    debug!("Content for source {} requested", id);
+
+    let ids: Vec<&str> = id.split(",").collect();
+    get_content(&ids, &filter, state, format)
+}
+
 pub fn get_source_content_text(
     id: web::Path<String>,
     filter: web::Query<QueryParameters>,
     state: web::Data<ServerState>,
 ) -> HttpResponse {
-    get_source_content(id, filter, state, false)
+    get_source_content(id, filter, state, ContentFormat::Text)
 }
 
 pub fn get_source_content_json(
@@ -201,9 +1263,694 @@ pub fn get_source_content_json(
     filter: web::Query<QueryParameters>,
     state: web::Data<ServerState>,
 ) -> HttpResponse {
-    get_source_content(id, filter, state, true)
+    get_source_content(id, filter, state, ContentFormat::Json)
+}
+
+pub fn get_source_content_ndjson(
+    id: web::Path<String>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_source_content(id, filter, state, ContentFormat::NdJson)
+}
+
+pub fn get_source_content_csv(
+    id: web::Path<String>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_source_content(id, filter, state, ContentFormat::Csv)
+}
+
+pub fn get_source_content_tsv(
+    id: web::Path<String>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_source_content(id, filter, state, ContentFormat::Tsv)
+}
+
+pub fn get_source_content_msgpack(
+    id: web::Path<String>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_source_content(id, filter, state, ContentFormat::MsgPack)
+}
+
+pub fn get_source_content_protobuf(
+    id: web::Path<String>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_source_content(id, filter, state, ContentFormat::Protobuf)
+}
+
+pub fn get_source_content_otlp(
+    id: web::Path<String>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_source_content(id, filter, state, ContentFormat::Otlp)
+}
+
+/// Live tail of the given sources as server-sent events, watching unless `watch=false` is given.
+/// Each event carries the cursor as id, so reconnecting clients resume after the last received entry
+/// by sending it as `Last-Event-ID`, which takes precedence over a `cursor` of the reconnected url.
+pub fn get_source_events(
+    req: HttpRequest,
+    id: web::Path<String>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    debug!("Events for source {} requested", id);
+
+    let mut filter = filter.into_inner();
+    if let Some(last_event_id) = req
+        .headers()
+        .get("Last-Event-ID")
+        .and_then(|value| value.to_str().ok())
+    {
+        filter.cursor = Some(last_event_id.to_string());
+    }
+    if filter.watch.is_none() {
+        filter.watch = Some(true);
+    }
+
+    let ids: Vec<&str> = id.split(",").collect();
+    get_content(&ids, &filter, state, ContentFormat::Events)
+}
+
+/// Live tail over a websocket, the client subscribes to sources and changes filters with json messages.
+pub fn get_websocket(
+    req: HttpRequest,
+    payload: web::Payload,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    match ws::handshake(req.head()) {
+        Ok(mut response) => response.streaming(WsSession::new(payload, state.get_ref().clone())),
+        Err(e) => e.error_response(),
+    }
+}
+
+fn labels_from_query(
+    parameters: &SelectionParameters,
+) -> Result<Vec<(&str, &str)>, ApplicationError> {
+    match &parameters.labels {
+        Some(labels) => labels
+            .split(',')
+            .map(|label| {
+                let mut parts = label.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(key), Some(value)) if !key.is_empty() => Ok((key, value)),
+                    _ => Err(ApplicationError::InvalidQuery(format!(
+                        "Invalid label, expected key=value: {}",
+                        label
+                    ))),
+                }
+            })
+            .collect(),
+        None => Ok(Vec::new()),
+    }
+}
+
+/// Merged content of all sources selected by id patterns and labels.
+/// No matching source results in an empty response, as other hosts may have them.
+fn get_selected_content(
+    selection: web::Query<SelectionParameters>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+    format: ContentFormat,
+) -> HttpResponse {
+    let patterns: Vec<&str> = match &selection.sources {
+        Some(sources) => sources.split(',').collect(),
+        None => Vec::new(),
+    };
+    let labels = match labels_from_query(&selection) {
+        Ok(labels) => labels,
+        Err(e) => return e.error_response(),
+    };
+    let ids = state.select_sources(&patterns, &labels);
+    debug!("Content for sources {:?} requested", ids);
+
+    let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+    get_content(&ids, &filter, state, format)
+}
+
+pub fn get_content_text(
+    selection: web::Query<SelectionParameters>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_selected_content(selection, filter, state, ContentFormat::Text)
+}
+
+pub fn get_content_json(
+    selection: web::Query<SelectionParameters>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_selected_content(selection, filter, state, ContentFormat::Json)
+}
+
+pub fn get_content_ndjson(
+    selection: web::Query<SelectionParameters>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_selected_content(selection, filter, state, ContentFormat::NdJson)
+}
+
+pub fn get_content_csv(
+    selection: web::Query<SelectionParameters>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_selected_content(selection, filter, state, ContentFormat::Csv)
+}
+
+pub fn get_content_tsv(
+    selection: web::Query<SelectionParameters>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_selected_content(selection, filter, state, ContentFormat::Tsv)
+}
+
+pub fn get_content_msgpack(
+    selection: web::Query<SelectionParameters>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_selected_content(selection, filter, state, ContentFormat::MsgPack)
 }
 
-// #[cfg(test)]
-// mod tests {
-// }
+pub fn get_content_protobuf(
+    selection: web::Query<SelectionParameters>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_selected_content(selection, filter, state, ContentFormat::Protobuf)
+}
+
+pub fn get_content_otlp(
+    selection: web::Query<SelectionParameters>,
+    filter: web::Query<QueryParameters>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_selected_content(selection, filter, state, ContentFormat::Otlp)
+}
+
+#[derive(Serialize, Debug)]
+struct SavedQueryRepr<'a> {
+    name: &'a str,
+    parameters: &'a BTreeMap<String, String>,
+}
+
+pub fn get_queries(state: web::Data<ServerState>) -> HttpResponse {
+    let queries = state.get_queries();
+    let dto: Vec<SavedQueryRepr> = queries
+        .iter()
+        .map(|query| SavedQueryRepr {
+            name: &query.name,
+            parameters: &query.parameters,
+        })
+        .collect();
+    HttpResponse::Ok().json(dto)
+}
+
+fn parse_parameters<T>(parameters: &BTreeMap<String, String>) -> Result<T, ApplicationError>
+where
+    T: serde::de::DeserializeOwned,
+{
+    serde_urlencoded::to_string(parameters)
+        .map_err(|e| e.to_string())
+        .and_then(|query| serde_urlencoded::from_str(&query).map_err(|e| e.to_string()))
+        .map_err(|e| ApplicationError::InvalidQuery(format!("Invalid query parameters: {}", e)))
+}
+
+/// Check the parameters of a saved query, as far as possible without a request.
+pub fn check_saved_query(query: &SavedQuery) -> Result<(), ApplicationError> {
+    let filter: QueryParameters = parse_parameters(&query.parameters)?;
+    let selection: SelectionParameters = parse_parameters(&query.parameters)?;
+    logfilter_from_query(&filter)?;
+    limits_from_query(&filter)?;
+    prefix_from_query(&filter)?;
+    labels_from_query(&selection)?;
+    if let Some(fields) = &filter.fields {
+        Projection::parse(fields)?;
+    }
+    if let Some(template) = &filter.template {
+        Template::parse(template)?;
+    }
+    Ok(())
+}
+
+// alternative parameters for the start and the end of the time range, only one of each may be given
+const RANGE_START_KEYS: [&str; 3] = ["from_ms", "from", "since"];
+const RANGE_END_KEYS: [&str; 2] = ["to_ms", "until"];
+
+/// Parameters of a saved query with those of the request, see `get_query_content`.
+fn merge_parameters(
+    saved: &BTreeMap<String, String>,
+    request: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut parameters = saved.clone();
+    // a bound of the requested range replaces the saved one, whichever parameter it is given by
+    for keys in &[&RANGE_START_KEYS[..], &RANGE_END_KEYS[..]] {
+        if keys.iter().any(|key| request.contains_key(*key)) {
+            for key in keys.iter() {
+                parameters.remove(*key);
+            }
+        }
+    }
+    for (key, value) in request.iter() {
+        match key.as_str() {
+            "sources" => {}
+            "labels" => {
+                let labels = match parameters.get("labels") {
+                    Some(saved) => format!("{},{}", saved, value),
+                    None => value.clone(),
+                };
+                parameters.insert(key.clone(), labels);
+            }
+            _ => {
+                parameters.insert(key.clone(), value.clone());
+            }
+        }
+    }
+    parameters
+}
+
+/// Content of a saved query. Parameters of the request override the saved ones,
+/// except for sources and labels, which narrow the saved selection.
+fn get_query_content(
+    name: web::Path<String>,
+    request: web::Query<BTreeMap<String, String>>,
+    state: web::Data<ServerState>,
+    format: ContentFormat,
+) -> HttpResponse {
+    debug!("Content for query {} requested", name);
+
+    let query = match state.lookup_query(&name) {
+        Some(query) => query,
+        None => return ApplicationError::QueryNotFound.error_response(),
+    };
+    let parameters = merge_parameters(&query.parameters, &request);
+
+    let selection: SelectionParameters = match parse_parameters(&parameters) {
+        Ok(selection) => selection,
+        Err(e) => return e.error_response(),
+    };
+    let filter: QueryParameters = match parse_parameters(&parameters) {
+        Ok(filter) => filter,
+        Err(e) => return e.error_response(),
+    };
+    let labels = match labels_from_query(&selection) {
+        Ok(labels) => labels,
+        Err(e) => return e.error_response(),
+    };
+    let patterns = |sources: Option<&String>| -> Vec<String> {
+        match sources {
+            Some(sources) => sources.split(',').map(str::to_string).collect(),
+            None => Vec::new(),
+        }
+    };
+    let saved_patterns = patterns(selection.sources.as_ref());
+    let request_patterns = patterns(request.get("sources"));
+    let ids: Vec<String> = state
+        .select_sources(
+            &saved_patterns
+                .iter()
+                .map(String::as_str)
+                .collect::<Vec<_>>(),
+            &labels,
+        )
+        .into_iter()
+        .filter(|id| {
+            request_patterns.is_empty()
+                || request_patterns
+                    .iter()
+                    .any(|pattern| util::wildcard_match(pattern, id))
+        })
+        .collect();
+
+    let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+    get_content(&ids, &filter, state, format)
+}
+
+pub fn get_query_content_text(
+    name: web::Path<String>,
+    request: web::Query<BTreeMap<String, String>>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_query_content(name, request, state, ContentFormat::Text)
+}
+
+pub fn get_query_content_json(
+    name: web::Path<String>,
+    request: web::Query<BTreeMap<String, String>>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_query_content(name, request, state, ContentFormat::Json)
+}
+
+pub fn get_query_content_ndjson(
+    name: web::Path<String>,
+    request: web::Query<BTreeMap<String, String>>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_query_content(name, request, state, ContentFormat::NdJson)
+}
+
+pub fn get_query_content_csv(
+    name: web::Path<String>,
+    request: web::Query<BTreeMap<String, String>>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_query_content(name, request, state, ContentFormat::Csv)
+}
+
+pub fn get_query_content_tsv(
+    name: web::Path<String>,
+    request: web::Query<BTreeMap<String, String>>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_query_content(name, request, state, ContentFormat::Tsv)
+}
+
+pub fn get_query_content_msgpack(
+    name: web::Path<String>,
+    request: web::Query<BTreeMap<String, String>>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_query_content(name, request, state, ContentFormat::MsgPack)
+}
+
+pub fn get_query_content_protobuf(
+    name: web::Path<String>,
+    request: web::Query<BTreeMap<String, String>>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_query_content(name, request, state, ContentFormat::Protobuf)
+}
+
+pub fn get_query_content_otlp(
+    name: web::Path<String>,
+    request: web::Query<BTreeMap<String, String>>,
+    state: web::Data<ServerState>,
+) -> HttpResponse {
+    get_query_content(name, request, state, ContentFormat::Otlp)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cursor::Cursor;
+    use crate::data::fixtures::entry;
+    use crate::data::{ApplicationError, LogStream};
+    use crate::data::{Marker, MarkerReason, ParsedLine, Repeat, StreamEntry};
+    use crate::log_merge::LogMerge;
+    use crate::logsource_port::{
+        aggregate, logfilter_from_query, merge_parameters, parse_parameters, parse_range,
+        ByteRange, Projection, QueryParameters,
+    };
+    use crate::proto;
+    use crate::util;
+    use async_std::task;
+    use futures::stream::{self, StreamExt};
+    use prost::Message;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+
+    fn entries() -> Vec<StreamEntry> {
+        let entry = entry("failed");
+        vec![
+            entry.clone(),
+            StreamEntry {
+                parsed_line: ParsedLine {
+                    loglevel: None,
+                    fields: Default::default(),
+                    ..entry.parsed_line.clone()
+                },
+                context: true,
+                repeat: Some(Repeat {
+                    count: 3,
+                    first_ts: 1_546_333_201_000,
+                    last_ts: 1_546_333_203_000,
+                }),
+                ..entry.clone()
+            },
+            StreamEntry {
+                marker: Some(Marker {
+                    reason: MarkerReason::MaxRate,
+                    skipped: Some(7),
+                }),
+                origin: None,
+                ..entry
+            },
+        ]
+    }
+
+    fn without_nulls(value: Value) -> Value {
+        match value {
+            Value::Object(map) => Value::Object(
+                map.into_iter()
+                    .filter(|(_, v)| !v.is_null())
+                    .map(|(k, v)| (k, without_nulls(v)))
+                    .collect(),
+            ),
+            value => value,
+        }
+    }
+
+    // the json object with the same content as the decoded protobuf entry
+    fn proto_as_json(entry: proto::Entry) -> Value {
+        let mut json = json!({ "cursor": entry.cursor });
+        let object = json.as_object_mut().unwrap();
+        let mut set = |key: &str, value: Option<Value>| {
+            if let Some(value) = value {
+                object.insert(key.to_string(), value);
+            }
+        };
+        set("timestamp", entry.timestamp.map(Value::from));
+        set("loglevel", entry.loglevel.map(Value::from));
+        set("message", entry.message.map(Value::from));
+        if !entry.fields.is_empty() {
+            set("fields", Some(json!(entry.fields)));
+        }
+        set("raw", entry.raw.map(Value::from));
+        set("source", entry.source.map(Value::from));
+        set("file", entry.file.map(Value::from));
+        set("line", entry.line.map(Value::from));
+        set(
+            "context",
+            Some(entry.context).filter(|c| *c).map(Value::from),
+        );
+        set(
+            "marker",
+            entry.marker.map(|marker| {
+                let reason = match proto::MarkerReason::from_i32(marker.reason) {
+                    Some(proto::MarkerReason::Sample) => "sample",
+                    Some(proto::MarkerReason::MaxRate) => "max_rate",
+                    Some(proto::MarkerReason::MaxLines) => "max_lines",
+                    Some(proto::MarkerReason::Dropped) => "dropped",
+                    Some(proto::MarkerReason::Failed) => "failed",
+                    _ => "unspecified",
+                };
+                without_nulls(json!({ "reason": reason, "skipped": marker.skipped }))
+            }),
+        );
+        if let Some(repeat) = entry.repeat {
+            set("repeat_count", Some(repeat.count.into()));
+            set("first_ts", Some(repeat.first_ts.into()));
+            set("last_ts", Some(repeat.last_ts.into()));
+        }
+        json
+    }
+
+    #[test]
+    fn test_msgpack_equals_json() {
+        let projection = Projection {
+            raw: true,
+            source: true,
+            file: true,
+            line: true,
+            ..Projection::default()
+        };
+        for (idx, stream_entry) in entries().iter().enumerate() {
+            let entry = projection.apply(stream_entry, 1_546_333_201_000, idx.to_string());
+            let json = serde_json::to_value(&entry).unwrap();
+            let msgpack = rmp_serde::to_vec_named(&entry).unwrap();
+            let decoded: Value = rmp_serde::from_slice(&msgpack).unwrap();
+            assert_eq!(decoded, json);
+        }
+    }
+
+    #[test]
+    fn test_protobuf_equals_json() {
+        let projection = Projection {
+            raw: true,
+            source: true,
+            file: true,
+            line: true,
+            ..Projection::default()
+        };
+        for (idx, stream_entry) in entries().iter().enumerate() {
+            let entry = projection.apply(stream_entry, 1_546_333_201_000, idx.to_string());
+            let json = serde_json::to_value(&entry).unwrap();
+            let mut frame = proto::frame(proto::FrameKind::Entry(Box::new(proto::Entry::from(
+                &entry,
+            ))));
+            let decoded = match proto::Frame::decode_length_delimited(&mut frame)
+                .unwrap()
+                .kind
+            {
+                Some(proto::FrameKind::Entry(entry)) => *entry,
+                kind => panic!("Unexpected frame {:?}", kind),
+            };
+            assert!(frame.is_empty());
+            assert_eq!(proto_as_json(decoded), without_nulls(json));
+        }
+    }
+
+    #[test]
+    fn test_parse_projection() {
+        let projection = Projection::parse("timestamp, raw,thread").unwrap();
+        assert!(projection.timestamp && projection.raw);
+        assert!(!projection.loglevel && !projection.message && !projection.source);
+        assert_eq!(projection.captures, Some(vec!["thread".to_string()]));
+        assert!(Projection::parse("timestamp,,raw").is_err());
+
+        let captures = vec!["thread".to_string(), "pid".to_string()];
+        assert!(projection.check_captures(&captures).is_ok());
+        assert!(projection.check_captures(&[]).is_err());
+        assert!(Projection::default().check_captures(&[]).is_ok());
+
+        let named_group = util::capture_names(
+            r"%{TIMESTAMP_ISO8601:timestamp} (?<pid>\d+) %{GREEDYDATA:message}",
+        );
+        assert!(Projection::parse("timestamp,pid")
+            .unwrap()
+            .check_captures(&named_group)
+            .is_ok());
+    }
+
+    #[test]
+    fn test_apply_projection() {
+        let entry = &entries()[0];
+        let projection = Projection::parse("message,line").unwrap();
+        let json = serde_json::to_value(projection.apply(entry, 1000, "c".to_string())).unwrap();
+        assert_eq!(json, json!({"message": "failed", "line": 3, "cursor": "c"}));
+
+        let projection = Projection::parse("loglevel,thread,source").unwrap();
+        let json = serde_json::to_value(projection.apply(entry, 1000, "c".to_string())).unwrap();
+        assert_eq!(
+            json,
+            json!({
+                "loglevel": "ERROR",
+                "fields": {"thread": "main"},
+                "source": "demo",
+                "cursor": "c"
+            })
+        );
+    }
+
+    #[test]
+    fn test_cursor_and_context_lines() {
+        let query = |pairs: &[(&str, &str)]| {
+            let parameters = pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect();
+            parse_parameters::<QueryParameters>(&parameters)
+                .and_then(|parameters| logfilter_from_query(&parameters))
+        };
+        let cursor = Cursor::default().encode();
+        let logfilter = query(&[("cursor", &cursor), ("after", "2"), ("before", "1")]).unwrap();
+        assert_eq!(logfilter.after, Some(Cursor::default()));
+        assert_eq!((logfilter.before_lines, logfilter.after_lines), (1, 2));
+
+        assert!(query(&[("cursor", "3")]).is_err());
+        // context lines are counts only
+        assert!(query(&[("after", &cursor)]).is_err());
+    }
+
+    #[test]
+    fn test_merge_parameters() {
+        let map = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect()
+        };
+        let saved = map(&[
+            ("since", "1h"),
+            ("to_ms", "2000"),
+            ("labels", "env=prod"),
+            ("sources", "web*"),
+        ]);
+
+        let merged = merge_parameters(&saved, &map(&[("from_ms", "1000")]));
+        assert_eq!(merged.get("from_ms"), Some(&"1000".to_string()));
+        assert_eq!(merged.get("since"), None);
+        assert_eq!(merged.get("to_ms"), Some(&"2000".to_string()));
+        let filter: QueryParameters = parse_parameters(&merged).unwrap();
+        assert!(logfilter_from_query(&filter).is_ok());
+
+        let merged = merge_parameters(&saved, &map(&[("until", "3000"), ("sources", "x")]));
+        assert_eq!(merged.get("since"), Some(&"1h".to_string()));
+        assert_eq!(merged.get("to_ms"), None);
+        assert_eq!(merged.get("until"), Some(&"3000".to_string()));
+        assert_eq!(merged.get("sources"), Some(&"web*".to_string()));
+
+        let merged = merge_parameters(&saved, &map(&[("labels", "tier=db")]));
+        assert_eq!(merged.get("labels"), Some(&"env=prod,tier=db".to_string()));
+    }
+
+    #[test]
+    fn test_parse_range() {
+        assert_eq!(parse_range("bytes=0-99", 1000), ByteRange::Partial(0, 99));
+        assert_eq!(
+            parse_range("bytes=900-", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        assert_eq!(
+            parse_range("bytes=900-2000", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        assert_eq!(
+            parse_range("bytes=-100", 1000),
+            ByteRange::Partial(900, 999)
+        );
+        assert_eq!(parse_range("bytes=-2000", 1000), ByteRange::Partial(0, 999));
+        assert_eq!(parse_range("bytes=1000-", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=-0", 1000), ByteRange::Unsatisfiable);
+        assert_eq!(parse_range("bytes=0-9,20-29", 1000), ByteRange::Full);
+        assert_eq!(parse_range("bytes=9-0", 1000), ByteRange::Full);
+        assert_eq!(parse_range("lines=0-9", 1000), ByteRange::Full);
+    }
+    #[test]
+    fn test_aggregate_failing_source() {
+        let complete: LogStream = stream::iter(vec![Ok(entry("a")), Ok(entry("b"))]).boxed_local();
+        let failing: LogStream = stream::iter(vec![
+            Ok(entry("c")),
+            Err(ApplicationError::FailedToReadSource),
+        ])
+        .boxed_local();
+        let merged = LogMerge::new(vec![complete, failing]);
+        let result = task::block_on(aggregate(merged, |stream_entry| {
+            assert!(stream_entry.marker.is_none());
+            Ok(())
+        }));
+        assert!(matches!(result, Err(ApplicationError::FailedToReadSource)));
+
+        let complete: LogStream = stream::iter(vec![Ok(entry("a")), Ok(entry("b"))]).boxed_local();
+        let mut counted = 0;
+        let result = task::block_on(aggregate(LogMerge::new(vec![complete]), |_| {
+            counted += 1;
+            Ok(())
+        }));
+        assert!(result.is_ok());
+        assert_eq!(counted, 2);
+    }
+}
@@ -2,8 +2,13 @@ use crate::data::ApplicationError;
 use crate::data::LogQueryContext;
 use crate::data::LogSource;
 use crate::data::LogStream;
+use crate::data::Order;
+use crate::log_context::LogContext;
+use crate::log_dedupe::LogDedupe;
+use crate::log_merge::LogMerge;
 use crate::log_source::FileSource;
 use crate::state;
+use futures::stream::StreamExt;
 use std::sync::Arc;
 
 pub struct LogSourceService;
@@ -17,16 +22,149 @@ impl LogSourceService {
         let state = state.clone();
 
         let lookup = state.lookup_source(id.as_ref());
-        match lookup {
+        let stream = match lookup {
             Some(logsource) => match logsource {
                 LogSource::File {
-                    id: _,
+                    id,
                     file_pattern,
                     line_pattern,
-                } => FileSource::create_stream(&file_pattern, &Arc::new(line_pattern), &logfilter),
+                    ..
+                } => FileSource::create_stream(
+                    &id,
+                    &file_pattern,
+                    &Arc::new(line_pattern),
+                    &logfilter,
+                    state.get_tails(),
+                ),
                 LogSource::Journal { .. } => unimplemented!(),
             },
             None => Err(ApplicationError::SourceNotFound),
+        }?;
+
+        // context windows and repeated lines may span files, so they are handled per source;
+        // runs of repeated lines are not collapsed across sources, as their cursor resumes one source
+        let stream = if logfilter.with_context_lines() {
+            // lines before a match in the file follow it in newest-first order
+            let (before, after) = match logfilter.order {
+                Order::Asc => (logfilter.before_lines, logfilter.after_lines),
+                Order::Desc => (logfilter.after_lines, logfilter.before_lines),
+            };
+            LogContext::new(stream, before, after).boxed_local()
+        } else {
+            stream
+        };
+        let stream = match logfilter.dedupe {
+            Some(mode) => LogDedupe::new(stream, mode).boxed_local(),
+            None => stream,
+        };
+        Ok(stream)
+    }
+
+    /// Create the content streams of all given sources, merged by timestamp in the requested order.
+    /// Fails with the error of the first source, whose stream cannot be created.
+    /// When watching, a source without new lines does not hold back the lines of the others.
+    pub fn create_merged_stream(
+        ids: &[&str],
+        state: state::ServerState,
+        logfilter: &Arc<LogQueryContext>,
+    ) -> Result<LogMerge, ApplicationError> {
+        let streams = ids
+            .iter()
+            .map(|id| Self::create_content_stream(String::from(*id), state.clone(), logfilter))
+            .collect::<Result<Vec<LogStream>, ApplicationError>>()?;
+        let merge = LogMerge::new(streams).with_order(logfilter.order);
+        if logfilter.watch == Some(true) {
+            Ok(merge.with_following())
+        } else {
+            Ok(merge)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{LinePattern, LogQueryContext, LogSource, Order};
+    use crate::log_source::{TailService, WatchConfig};
+    use crate::logsource_svc::LogSourceService;
+    use crate::state::ServerState;
+    use async_std::future::timeout;
+    use async_std::task;
+    use futures::stream::StreamExt;
+    use regex::Regex;
+    use std::collections::BTreeMap;
+    use std::io::Write;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    fn file_source(id: &str, path: &str, grok: &mut grok::Grok) -> LogSource {
+        let raw = "%{TIMESTAMP_ISO8601:timestamp} %{LOGLEVEL:loglevel} %{GREEDYDATA:message}";
+        LogSource::File {
+            id: id.to_string(),
+            file_pattern: Regex::new(&regex::escape(path)).unwrap(),
+            line_pattern: LinePattern {
+                raw: raw.to_string(),
+                grok: Arc::new(grok.compile(raw, true).unwrap()),
+                chrono: Arc::new("%Y-%m-%d %H:%M:%S".to_string()),
+                timezone: chrono_tz::UTC,
+                syslog_ts: false,
+            },
+            labels: BTreeMap::new(),
+            template: None,
         }
     }
+
+    #[test]
+    fn test_watch_with_quiet_source() {
+        let path = |name: &str| {
+            std::env::temp_dir()
+                .join(format!("tentacle-{}-{}.log", name, std::process::id()))
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+        let (quiet, active) = (path("merge-quiet"), path("merge-active"));
+        std::fs::write(&quiet, "2019-01-01 00:00:00 INFO quiet\n").unwrap();
+        std::fs::write(&active, "2019-01-01 00:00:01 INFO active 1\n").unwrap();
+
+        let mut grok = grok::Grok::default();
+        let sources = vec![
+            file_source("quiet", &quiet, &mut grok),
+            file_source("active", &active, &mut grok),
+        ];
+        let state = ServerState::new(sources, grok).with_tails(TailService::new(WatchConfig {
+            poll_interval: Duration::from_millis(50),
+            ..WatchConfig::default()
+        }));
+        let context = Arc::new(LogQueryContext {
+            from_ms: 0,
+            to_ms: None,
+            loglevels: None,
+            watch: Some(true),
+            after: None,
+            before_lines: 0,
+            after_lines: 0,
+            dedupe: None,
+            order: Order::Asc,
+        });
+        let mut merged =
+            LogSourceService::create_merged_stream(&["quiet", "active"], state, &context).unwrap();
+
+        let next_message = |merged: &mut crate::log_merge::LogMerge| {
+            task::block_on(timeout(Duration::from_secs(1), merged.next()))
+                .map(|entry| entry.unwrap().parsed_line.message)
+        };
+        assert_eq!(next_message(&mut merged).unwrap(), "quiet");
+        assert_eq!(next_message(&mut merged).unwrap(), "active 1");
+        let mut file = std::fs::OpenOptions::new()
+            .append(true)
+            .open(&active)
+            .unwrap();
+        file.write_all(b"2019-01-01 00:00:02 INFO active 2\n")
+            .unwrap();
+        assert_eq!(next_message(&mut merged).unwrap(), "active 2");
+        assert!(next_message(&mut merged).is_err());
+
+        std::fs::remove_file(quiet).unwrap();
+        std::fs::remove_file(active).unwrap();
+    }
 }
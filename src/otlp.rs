@@ -0,0 +1,276 @@
+use crate::constants::VERSION;
+use crate::data::{MarkerReason, StreamEntry};
+use bytes::Bytes;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Debug)]
+enum AnyValue<'a> {
+    #[serde(rename = "stringValue")]
+    String(&'a str),
+    #[serde(rename = "boolValue")]
+    Bool(bool),
+    #[serde(rename = "intValue")]
+    Int(String), // int64 values are strings in OTLP/JSON
+}
+
+#[derive(Serialize, Debug)]
+struct KeyValue<'a> {
+    key: &'a str,
+    value: AnyValue<'a>,
+}
+
+fn string_value<'a>(key: &'a str, value: &'a str) -> KeyValue<'a> {
+    KeyValue {
+        key,
+        value: AnyValue::String(value),
+    }
+}
+
+fn int_value(key: &str, value: u64) -> KeyValue<'_> {
+    KeyValue {
+        key,
+        value: AnyValue::Int(value.to_string()),
+    }
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LogsData<'a> {
+    resource_logs: [ResourceLogs<'a>; 1],
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ResourceLogs<'a> {
+    resource: Resource<'a>,
+    scope_logs: [ScopeLogs<'a>; 1],
+}
+
+#[derive(Serialize, Debug)]
+struct Resource<'a> {
+    attributes: Vec<KeyValue<'a>>,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct ScopeLogs<'a> {
+    scope: Scope,
+    log_records: [LogRecord<'a>; 1],
+}
+
+#[derive(Serialize, Debug)]
+struct Scope {
+    name: &'static str,
+    version: &'static str,
+}
+
+#[derive(Serialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct LogRecord<'a> {
+    time_unix_nano: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    severity_number: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    severity_text: Option<&'a str>,
+    body: AnyValue<'a>,
+    attributes: Vec<KeyValue<'a>>,
+}
+
+/// Severity number of the OpenTelemetry log data model, by the usual names of log levels.
+fn severity_number(loglevel: &str) -> Option<u8> {
+    match loglevel.to_ascii_uppercase().as_str() {
+        "TRACE" | "FINEST" => Some(1),
+        "DEBUG" | "FINE" => Some(5),
+        "INFO" => Some(9),
+        "NOTICE" => Some(10),
+        "WARN" | "WARNING" => Some(13),
+        "ERROR" | "ERR" => Some(17),
+        "CRIT" | "CRITICAL" | "SEVERE" | "ALERT" => Some(18),
+        "FATAL" | "EMERG" | "EMERGENCY" | "PANIC" => Some(21),
+        _ => None,
+    }
+}
+
+/// Entries as newline delimited OTLP/JSON `LogsData`, one per entry, as read by the otlpjsonfile receiver
+/// of the OpenTelemetry collector. The host, source id and labels of the source are the resource attributes.
+pub struct OtlpFormat {
+    host: String,
+    labels: BTreeMap<String, BTreeMap<String, String>>, // by source id
+}
+
+impl OtlpFormat {
+    pub fn new(host: String, labels: BTreeMap<String, BTreeMap<String, String>>) -> OtlpFormat {
+        OtlpFormat { host, labels }
+    }
+
+    /// The OTLP json line of an entry with its cursor as attribute. The record time is the given timestamp,
+    /// so the lines of a multiline message share the time of their first line.
+    pub fn entry(
+        &self,
+        entry: &StreamEntry,
+        timestamp: u128,
+        cursor: &str,
+    ) -> serde_json::Result<Bytes> {
+        let origin = entry.origin.as_ref();
+        let mut resource = vec![string_value("host.name", &self.host)];
+        if let Some(origin) = origin {
+            resource.push(string_value("tentacle.source.id", &origin.source));
+            if let Some(labels) = self.labels.get(origin.source.as_str()) {
+                resource.extend(labels.iter().map(|(key, value)| string_value(key, value)));
+            }
+        }
+
+        let parsed_line = &entry.parsed_line;
+        let mut attributes: Vec<KeyValue> = parsed_line
+            .fields
+            .iter()
+            .map(|(name, value)| string_value(name, value))
+            .collect();
+        if let Some(origin) = origin {
+            attributes.push(string_value("log.file.name", &origin.file));
+        }
+        attributes.push(string_value("tentacle.cursor", cursor));
+        if entry.context {
+            attributes.push(KeyValue {
+                key: "tentacle.context",
+                value: AnyValue::Bool(true),
+            });
+        }
+        if let Some(marker) = &entry.marker {
+            let reason = match marker.reason {
+                MarkerReason::Sample => "sample",
+                MarkerReason::MaxRate => "max_rate",
+                MarkerReason::MaxLines => "max_lines",
+                MarkerReason::Dropped => "dropped",
+                MarkerReason::Failed => "failed",
+            };
+            attributes.push(string_value("tentacle.marker.reason", reason));
+            if let Some(skipped) = marker.skipped {
+                attributes.push(int_value("tentacle.marker.skipped", skipped));
+            }
+        }
+        if let Some(repeat) = &entry.repeat {
+            attributes.push(int_value("tentacle.repeat.count", repeat.count));
+        }
+
+        let loglevel = parsed_line.loglevel.as_deref();
+        let data = LogsData {
+            resource_logs: [ResourceLogs {
+                resource: Resource {
+                    attributes: resource,
+                },
+                scope_logs: [ScopeLogs {
+                    scope: Scope {
+                        name: "tentacle",
+                        version: VERSION,
+                    },
+                    log_records: [LogRecord {
+                        time_unix_nano: (timestamp * 1_000_000).to_string(),
+                        severity_number: loglevel.and_then(severity_number),
+                        severity_text: loglevel,
+                        body: AnyValue::String(&parsed_line.message),
+                        attributes,
+                    }],
+                }],
+            }],
+        };
+        let mut line = serde_json::to_vec(&data)?;
+        line.push(b'\n');
+        Ok(Bytes::from(line))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{fixtures, Marker, MarkerReason, ParsedLine, StreamEntry};
+    use crate::otlp::OtlpFormat;
+    use serde_json::{json, Value};
+    use std::collections::BTreeMap;
+
+    fn entry() -> StreamEntry {
+        fixtures::entry("failed")
+    }
+
+    fn format() -> OtlpFormat {
+        let mut labels = BTreeMap::new();
+        labels.insert(
+            "demo".to_string(),
+            vec![("tier".to_string(), "web".to_string())]
+                .into_iter()
+                .collect(),
+        );
+        OtlpFormat::new("host1".to_string(), labels)
+    }
+
+    fn parse(line: &[u8]) -> Value {
+        assert_eq!(line.last(), Some(&b'\n'));
+        serde_json::from_slice(line).unwrap()
+    }
+
+    #[test]
+    fn test_log_record() {
+        let e = entry();
+        let line = format().entry(&e, e.parsed_line.timestamp, "c1").unwrap();
+        let resource_logs = &parse(&line)["resourceLogs"][0];
+        assert_eq!(
+            resource_logs["resource"]["attributes"],
+            json!([
+                {"key": "host.name", "value": {"stringValue": "host1"}},
+                {"key": "tentacle.source.id", "value": {"stringValue": "demo"}},
+                {"key": "tier", "value": {"stringValue": "web"}},
+            ])
+        );
+        let scope_logs = &resource_logs["scopeLogs"][0];
+        assert_eq!(scope_logs["scope"]["name"], "tentacle");
+        assert_eq!(
+            scope_logs["logRecords"][0],
+            json!({
+                "timeUnixNano": "1546333201000000000",
+                "severityNumber": 17,
+                "severityText": "ERROR",
+                "body": {"stringValue": "failed"},
+                "attributes": [
+                    {"key": "thread", "value": {"stringValue": "main"}},
+                    {"key": "log.file.name", "value": {"stringValue": "demo.log"}},
+                    {"key": "tentacle.cursor", "value": {"stringValue": "c1"}},
+                ]
+            })
+        );
+    }
+
+    #[test]
+    fn test_marker() {
+        let e = StreamEntry {
+            parsed_line: ParsedLine {
+                timestamp: 0,
+                loglevel: Some("notalevel".to_string()),
+                message: "left out".to_string(),
+                fields: BTreeMap::new(),
+            },
+            marker: Some(Marker {
+                reason: MarkerReason::MaxRate,
+                skipped: Some(7),
+            }),
+            origin: None,
+            ..entry()
+        };
+        let line = format().entry(&e, 5, "c2").unwrap();
+        let resource_logs = &parse(&line)["resourceLogs"][0];
+        assert_eq!(
+            resource_logs["resource"]["attributes"],
+            json!([{"key": "host.name", "value": {"stringValue": "host1"}}])
+        );
+        let record = &resource_logs["scopeLogs"][0]["logRecords"][0];
+        assert_eq!(record["timeUnixNano"], "5000000");
+        assert_eq!(record.get("severityNumber"), None);
+        assert_eq!(record["severityText"], "notalevel");
+        assert_eq!(
+            record["attributes"],
+            json!([
+                {"key": "tentacle.cursor", "value": {"stringValue": "c2"}},
+                {"key": "tentacle.marker.reason", "value": {"stringValue": "max_rate"}},
+                {"key": "tentacle.marker.skipped", "value": {"intValue": "7"}},
+            ])
+        );
+    }
+}
@@ -0,0 +1,226 @@
+use crate::data::{self, ApplicationError};
+use bytes::Bytes;
+use core::pin::Pin;
+use core::task::Context;
+use futures::stream::{Stream, StreamExt};
+use futures::task::Poll;
+use prost::Message;
+use std::collections::HashMap;
+
+/// Messages of the protobuf content encoding, as described by `proto/tentacle.proto`.
+#[derive(Clone, PartialEq, Message)]
+pub struct Frame {
+    #[prost(oneof = "FrameKind", tags = "1, 2")]
+    pub kind: Option<FrameKind>,
+}
+
+#[derive(Clone, PartialEq, prost::Oneof)]
+pub enum FrameKind {
+    #[prost(message, tag = "1")]
+    Entry(Box<Entry>),
+    #[prost(message, tag = "2")]
+    Trailer(Trailer),
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Entry {
+    #[prost(uint64, optional, tag = "1")]
+    pub timestamp: Option<u64>,
+    #[prost(string, optional, tag = "2")]
+    pub loglevel: Option<String>,
+    #[prost(string, optional, tag = "3")]
+    pub message: Option<String>,
+    #[prost(map = "string, string", tag = "4")]
+    pub fields: HashMap<String, String>,
+    #[prost(string, optional, tag = "5")]
+    pub raw: Option<String>,
+    #[prost(string, optional, tag = "6")]
+    pub source: Option<String>,
+    #[prost(string, optional, tag = "7")]
+    pub file: Option<String>,
+    #[prost(uint64, optional, tag = "8")]
+    pub line: Option<u64>,
+    #[prost(string, tag = "9")]
+    pub cursor: String,
+    #[prost(bool, tag = "10")]
+    pub context: bool,
+    #[prost(message, optional, tag = "11")]
+    pub marker: Option<Marker>,
+    #[prost(message, optional, tag = "12")]
+    pub repeat: Option<Repeat>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, prost::Enumeration)]
+#[repr(i32)]
+pub enum MarkerReason {
+    Unspecified = 0,
+    Sample = 1,
+    MaxRate = 2,
+    MaxLines = 3,
+    Dropped = 4,
+    Failed = 5,
+}
+
+impl From<data::MarkerReason> for MarkerReason {
+    fn from(reason: data::MarkerReason) -> Self {
+        match reason {
+            data::MarkerReason::Sample => MarkerReason::Sample,
+            data::MarkerReason::MaxRate => MarkerReason::MaxRate,
+            data::MarkerReason::MaxLines => MarkerReason::MaxLines,
+            data::MarkerReason::Dropped => MarkerReason::Dropped,
+            data::MarkerReason::Failed => MarkerReason::Failed,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Marker {
+    #[prost(enumeration = "MarkerReason", tag = "1")]
+    pub reason: i32,
+    #[prost(uint64, optional, tag = "2")]
+    pub skipped: Option<u64>,
+}
+
+impl From<&data::Marker> for Marker {
+    fn from(marker: &data::Marker) -> Self {
+        Marker {
+            reason: MarkerReason::from(marker.reason) as i32,
+            skipped: marker.skipped,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Repeat {
+    #[prost(uint64, tag = "1")]
+    pub count: u64,
+    #[prost(uint64, tag = "2")]
+    pub first_ts: u64,
+    #[prost(uint64, tag = "3")]
+    pub last_ts: u64,
+}
+
+impl From<&data::Repeat> for Repeat {
+    fn from(repeat: &data::Repeat) -> Self {
+        Repeat {
+            count: repeat.count,
+            first_ts: repeat.first_ts as u64,
+            last_ts: repeat.last_ts as u64,
+        }
+    }
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Trailer {
+    #[prost(uint64, tag = "1")]
+    pub entries: u64,
+}
+
+/// Encode a frame preceded by its length.
+pub fn frame(kind: FrameKind) -> Bytes {
+    let frame = Frame { kind: Some(kind) };
+    let mut buf = Vec::with_capacity(frame.encoded_len() + 4);
+    // a vec grows as needed, so encoding cannot fail
+    frame
+        .encode_length_delimited(&mut buf)
+        .expect("Failed to encode frame");
+    Bytes::from(buf)
+}
+
+/// Appends the trailer to a stream of entry frames, once the stream ends without error.
+pub struct Trailed<S> {
+    source: S,
+    entries: u64,
+    done: bool,
+}
+
+impl<S> Trailed<S>
+where
+    S: Stream<Item = Result<Bytes, ApplicationError>> + Unpin,
+{
+    pub fn new(source: S) -> Trailed<S> {
+        Trailed {
+            source,
+            entries: 0,
+            done: false,
+        }
+    }
+}
+
+impl<S> Stream for Trailed<S>
+where
+    S: Stream<Item = Result<Bytes, ApplicationError>> + Unpin,
+{
+    type Item = Result<Bytes, ApplicationError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        if self.done {
+            return Poll::Ready(None);
+        }
+        match self.source.poll_next_unpin(cx) {
+            Poll::Ready(Some(Ok(entry))) => {
+                self.entries += 1;
+                Poll::Ready(Some(Ok(entry)))
+            }
+            Poll::Ready(Some(Err(e))) => {
+                self.done = true;
+                Poll::Ready(Some(Err(e)))
+            }
+            Poll::Ready(None) => {
+                self.done = true;
+                let trailer = Trailer {
+                    entries: self.entries,
+                };
+                Poll::Ready(Some(Ok(frame(FrameKind::Trailer(trailer)))))
+            }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::ApplicationError;
+    use crate::proto::{frame, Entry, Frame, FrameKind, Trailed, Trailer};
+    use async_std::task;
+    use bytes::Bytes;
+    use futures::stream::{self, StreamExt};
+    use prost::Message;
+
+    fn entry(cursor: &str) -> Bytes {
+        frame(FrameKind::Entry(Box::new(Entry {
+            cursor: cursor.to_string(),
+            ..Entry::default()
+        })))
+    }
+
+    #[test]
+    fn test_trailer() {
+        let entries: Vec<Result<Bytes, ApplicationError>> = vec![Ok(entry("a")), Ok(entry("b"))];
+        let chunks: Vec<Bytes> = task::block_on(
+            Trailed::new(stream::iter(entries))
+                .map(Result::unwrap)
+                .collect(),
+        );
+        let mut buf = Bytes::from(chunks.concat());
+        let mut frames = Vec::new();
+        while !buf.is_empty() {
+            frames.push(Frame::decode_length_delimited(&mut buf).unwrap());
+        }
+        assert_eq!(frames.len(), 3);
+        assert_eq!(
+            frames[2].kind,
+            Some(FrameKind::Trailer(Trailer { entries: 2 }))
+        );
+    }
+
+    #[test]
+    fn test_no_trailer_after_error() {
+        let entries: Vec<Result<Bytes, ApplicationError>> =
+            vec![Ok(entry("a")), Err(ApplicationError::FailedToReadSource)];
+        let chunks: Vec<Result<Bytes, ApplicationError>> =
+            task::block_on(Trailed::new(stream::iter(entries)).collect());
+        assert_eq!(chunks.len(), 2);
+        assert!(chunks[1].is_err());
+    }
+}
@@ -8,11 +8,18 @@ use actix_web::HttpRequest;
 use actix_web::HttpResponse;
 use config;
 
+use crate::compression;
+use crate::compression::CompressionConfig;
 use crate::constants::*;
 use crate::data::LogSource;
 use crate::data::LogSourceBuilder;
+use crate::data::SavedQuery;
+use crate::data::SavedQueryBuilder;
+use crate::log_source::TailService;
+use crate::log_source::WatchConfig;
 use crate::logsource_port;
 use crate::state::ServerState;
+use crate::template::Template;
 
 fn parse_source_config(settings: &config::Config, grok: &mut grok::Grok) -> Vec<LogSource> {
     if let Ok(array) = settings.get_array("sources") {
@@ -29,6 +36,29 @@ fn parse_source_config(settings: &config::Config, grok: &mut grok::Grok) -> Vec<
     }
 }
 
+fn parse_template_config(settings: &config::Config) -> Option<Template> {
+    settings.get_str("template").ok().map(|template| {
+        Template::parse(&template).unwrap_or_else(|e| panic!("Invalid default template: {}", e))
+    })
+}
+
+fn parse_query_config(settings: &config::Config) -> Vec<SavedQuery> {
+    if let Ok(array) = settings.get_array("queries") {
+        array
+            .iter()
+            .map(|v| {
+                let query = SavedQueryBuilder::create(v).unwrap();
+                if let Err(e) = logsource_port::check_saved_query(&query) {
+                    panic!("Invalid saved query {}: {}", query.name, e);
+                }
+                query
+            })
+            .collect()
+    } else {
+        vec![]
+    }
+}
+
 pub fn start_server(settings: &config::Config) {
     let port = settings.get_int("http.bind.port").unwrap();
     let ip = settings.get_str("http.bind.ip").unwrap();
@@ -36,11 +66,16 @@ pub fn start_server(settings: &config::Config) {
 
     let mut grok = grok::Grok::default();
 
-    let server_state = ServerState::new(parse_source_config(&settings, &mut grok), grok);
+    let server_state = ServerState::new(parse_source_config(&settings, &mut grok), grok)
+        .with_queries(parse_query_config(settings))
+        .with_template(parse_template_config(settings))
+        .with_tails(TailService::new(WatchConfig::from_settings(settings)));
+    let compression = CompressionConfig::from_settings(settings);
 
     actix_web::HttpServer::new(move || {
         actix_web::App::new()
             .wrap(middleware::Logger::default())
+            .wrap_fn(move |req, srv| compression::compress(req, srv, compression))
             .data(server_state.clone())
             .service(
                 web::scope("/api/v1")
@@ -65,9 +100,180 @@ pub fn start_server(settings: &config::Config) {
                             .guard(guard::Header("accept", "application/json"))
                             .to(logsource_port::get_source_content_json),
                     )
+                    .route(
+                        "/sources/{id}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/x-ndjson"))
+                            .to(logsource_port::get_source_content_ndjson),
+                    )
+                    .route(
+                        "/sources/{id}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "text/csv"))
+                            .to(logsource_port::get_source_content_csv),
+                    )
+                    .route(
+                        "/sources/{id}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "text/tab-separated-values"))
+                            .to(logsource_port::get_source_content_tsv),
+                    )
+                    .route(
+                        "/sources/{id}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/msgpack"))
+                            .to(logsource_port::get_source_content_msgpack),
+                    )
+                    .route(
+                        "/sources/{id}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/x-protobuf"))
+                            .to(logsource_port::get_source_content_protobuf),
+                    )
+                    .route(
+                        "/sources/{id}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/x-otlp+json"))
+                            .to(logsource_port::get_source_content_otlp),
+                    )
                     .route(
                         "/sources/{id}/content",
                         web::get().to(|| HttpResponse::NotAcceptable()),
+                    )
+                    .route(
+                        "/content",
+                        web::get()
+                            .guard(guard::Header("accept", "*/*"))
+                            .to(logsource_port::get_content_text),
+                    )
+                    .route(
+                        "/content",
+                        web::get()
+                            .guard(guard::Header("accept", "text/plain"))
+                            .to(logsource_port::get_content_text),
+                    )
+                    .route(
+                        "/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/json"))
+                            .to(logsource_port::get_content_json),
+                    )
+                    .route(
+                        "/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/x-ndjson"))
+                            .to(logsource_port::get_content_ndjson),
+                    )
+                    .route(
+                        "/content",
+                        web::get()
+                            .guard(guard::Header("accept", "text/csv"))
+                            .to(logsource_port::get_content_csv),
+                    )
+                    .route(
+                        "/content",
+                        web::get()
+                            .guard(guard::Header("accept", "text/tab-separated-values"))
+                            .to(logsource_port::get_content_tsv),
+                    )
+                    .route(
+                        "/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/msgpack"))
+                            .to(logsource_port::get_content_msgpack),
+                    )
+                    .route(
+                        "/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/x-protobuf"))
+                            .to(logsource_port::get_content_protobuf),
+                    )
+                    .route(
+                        "/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/x-otlp+json"))
+                            .to(logsource_port::get_content_otlp),
+                    )
+                    .route("/content", web::get().to(HttpResponse::NotAcceptable))
+                    .route("/queries", web::get().to(logsource_port::get_queries))
+                    .route(
+                        "/queries/{name}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "*/*"))
+                            .to(logsource_port::get_query_content_text),
+                    )
+                    .route(
+                        "/queries/{name}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "text/plain"))
+                            .to(logsource_port::get_query_content_text),
+                    )
+                    .route(
+                        "/queries/{name}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/json"))
+                            .to(logsource_port::get_query_content_json),
+                    )
+                    .route(
+                        "/queries/{name}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/x-ndjson"))
+                            .to(logsource_port::get_query_content_ndjson),
+                    )
+                    .route(
+                        "/queries/{name}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "text/csv"))
+                            .to(logsource_port::get_query_content_csv),
+                    )
+                    .route(
+                        "/queries/{name}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "text/tab-separated-values"))
+                            .to(logsource_port::get_query_content_tsv),
+                    )
+                    .route(
+                        "/queries/{name}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/msgpack"))
+                            .to(logsource_port::get_query_content_msgpack),
+                    )
+                    .route(
+                        "/queries/{name}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/x-protobuf"))
+                            .to(logsource_port::get_query_content_protobuf),
+                    )
+                    .route(
+                        "/queries/{name}/content",
+                        web::get()
+                            .guard(guard::Header("accept", "application/x-otlp+json"))
+                            .to(logsource_port::get_query_content_otlp),
+                    )
+                    .route(
+                        "/queries/{name}/content",
+                        web::get().to(HttpResponse::NotAcceptable),
+                    )
+                    .route(
+                        "/sources/{id}/events",
+                        web::get().to(logsource_port::get_source_events),
+                    )
+                    .route("/ws", web::get().to(logsource_port::get_websocket))
+                    .route(
+                        "/sources/{id}/files",
+                        web::get().to(logsource_port::get_source_files),
+                    )
+                    .route(
+                        "/sources/{id}/files/{name}",
+                        web::get().to(logsource_port::get_source_file),
+                    )
+                    .route(
+                        "/sources/{id}/histogram",
+                        web::get().to(logsource_port::get_source_histogram),
+                    )
+                    .route(
+                        "/sources/{id}/facets",
+                        web::get().to(logsource_port::get_source_facets),
                     ),
             )
             .service(
@@ -104,6 +310,22 @@ mod tests {
         assert_eq!(resp, super::WELCOME_MSG);
     }
 
+    #[test]
+    fn test_parse_query_config() {
+        let settings = crate::cfg::read_config(&Some("tests/integrationtests.yml")).unwrap();
+        let queries = super::parse_query_config(&settings);
+        assert_eq!(queries.len(), 1);
+        assert_eq!(queries[0].name, "demo-errors");
+        assert_eq!(
+            queries[0].parameters.get("loglevels"),
+            Some(&"ERROR,WARN".to_string())
+        );
+        assert_eq!(
+            queries[0].parameters.get("sources"),
+            Some(&"it*".to_string())
+        );
+    }
+
     #[actix_rt::test]
     async fn test_health_handler() {
         let state = super::ServerState::new(vec![], grok::Grok::default());
@@ -0,0 +1,117 @@
+use crate::data::ApplicationError;
+use bytes::Bytes;
+use core::pin::Pin;
+use core::task::Context;
+use futures::future::Future;
+use futures::stream::{Stream, StreamExt};
+use futures::task::Poll;
+use std::time::Duration;
+
+// idle connections get a comment after this period, so proxies do not close them
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(15);
+
+const HEARTBEAT: &[u8] = b": heartbeat\n\n";
+
+/// Frame data as server-sent event. Line breaks in the data are sent as multiple data fields.
+pub fn event(id: Option<&str>, event_type: Option<&str>, data: &str) -> Bytes {
+    let mut frame = String::with_capacity(data.len() + 64);
+    if let Some(event_type) = event_type {
+        frame.push_str("event: ");
+        frame.push_str(event_type);
+        frame.push('\n');
+    }
+    if let Some(id) = id {
+        frame.push_str("id: ");
+        frame.push_str(id);
+        frame.push('\n');
+    }
+    for line in data.split('\n') {
+        frame.push_str("data: ");
+        frame.push_str(line);
+        frame.push('\n');
+    }
+    frame.push('\n');
+    Bytes::from(frame)
+}
+
+/// Sends a comment whenever the source did not deliver anything for the given interval.
+pub struct Heartbeat<S> {
+    source: S,
+    interval: Duration,
+    timer: Option<Pin<Box<dyn Future<Output = ()>>>>,
+}
+
+impl<S> Heartbeat<S>
+where
+    S: Stream<Item = Result<Bytes, ApplicationError>> + Unpin,
+{
+    pub fn new(source: S, interval: Duration) -> Heartbeat<S> {
+        Heartbeat {
+            source,
+            interval,
+            timer: None,
+        }
+    }
+}
+
+impl<S> Stream for Heartbeat<S>
+where
+    S: Stream<Item = Result<Bytes, ApplicationError>> + Unpin,
+{
+    type Item = Result<Bytes, ApplicationError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        match self.source.poll_next_unpin(cx) {
+            Poll::Pending => {
+                let interval = self.interval;
+                let timer = self
+                    .timer
+                    .get_or_insert_with(|| Box::pin(async_std::task::sleep(interval)));
+                if timer.as_mut().poll(cx).is_ready() {
+                    self.timer = None;
+                    Poll::Ready(Some(Ok(Bytes::from_static(HEARTBEAT))))
+                } else {
+                    Poll::Pending
+                }
+            }
+            ready => {
+                self.timer = None;
+                ready
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::sse::{event, Heartbeat};
+    use async_std::task;
+    use bytes::Bytes;
+    use futures::stream;
+    use futures::stream::StreamExt;
+    use std::time::Duration;
+
+    #[test]
+    fn test_event() {
+        assert_eq!(
+            event(Some("c1"), None, "{\"a\":1}"),
+            Bytes::from("id: c1\ndata: {\"a\":1}\n\n")
+        );
+        assert_eq!(
+            event(None, Some("marker"), "two\nlines"),
+            Bytes::from("event: marker\ndata: two\ndata: lines\n\n")
+        );
+    }
+
+    #[test]
+    fn test_heartbeat_when_idle() {
+        let source = stream::iter(vec![Ok(Bytes::from("a"))]).chain(stream::pending());
+        let mut heartbeat = Heartbeat::new(source, Duration::from_millis(10));
+        let first = task::block_on(heartbeat.next()).unwrap().unwrap();
+        assert_eq!(first, Bytes::from("a"));
+        for _ in 0..2 {
+            let next = task::block_on(heartbeat.next()).unwrap().unwrap();
+            assert_eq!(next, Bytes::from(": heartbeat\n\n"));
+        }
+    }
+}
@@ -1,10 +1,18 @@
 use std::sync::Arc;
 
 use crate::data::LogSource;
+use crate::data::SavedQuery;
+use crate::log_source::TailService;
+use crate::template::Template;
+use crate::util;
 use grok::Grok;
+use std::collections::BTreeMap;
 
 pub struct ServerState {
     sources: Arc<Vec<LogSource>>,
+    queries: Arc<Vec<SavedQuery>>,
+    template: Option<Arc<Template>>, // default of the text output
+    tails: TailService,
     pub grok: Arc<Grok>,
 }
 
@@ -12,6 +20,9 @@ impl Clone for ServerState {
     fn clone(&self) -> Self {
         ServerState {
             sources: self.sources.clone(),
+            queries: self.queries.clone(),
+            template: self.template.clone(),
+            tails: self.tails.clone(),
             grok: self.grok.clone(),
         }
     }
@@ -21,22 +32,39 @@ impl ServerState {
     pub fn new(sources: Vec<LogSource>, grok: Grok) -> ServerState {
         ServerState {
             sources: Arc::new(sources),
+            queries: Arc::new(vec![]),
+            template: None,
+            tails: TailService::default(),
             grok: Arc::new(grok),
         }
     }
 
+    pub fn with_queries(mut self, queries: Vec<SavedQuery>) -> ServerState {
+        self.queries = Arc::new(queries);
+        self
+    }
+
+    pub fn with_template(mut self, template: Option<Template>) -> ServerState {
+        self.template = template.map(Arc::new);
+        self
+    }
+
+    pub fn with_tails(mut self, tails: TailService) -> ServerState {
+        self.tails = tails;
+        self
+    }
+
     fn extract_source_key(source: &LogSource) -> &String {
         match source {
-            LogSource::File {
-                id,
-                file_pattern: _,
-                line_pattern: _,
-            } => id,
-            LogSource::Journal {
-                id,
-                unit: _,
-                line_pattern: _,
-            } => id,
+            LogSource::File { id, .. } => id,
+            LogSource::Journal { id, .. } => id,
+        }
+    }
+
+    fn extract_source_labels(source: &LogSource) -> &BTreeMap<String, String> {
+        match source {
+            LogSource::File { labels, .. } => labels,
+            LogSource::Journal { labels, .. } => labels,
         }
     }
 
@@ -44,6 +72,22 @@ impl ServerState {
         self.sources.clone()
     }
 
+    pub fn get_queries(&self) -> Arc<Vec<SavedQuery>> {
+        self.queries.clone()
+    }
+
+    pub fn get_template(&self) -> Option<Template> {
+        self.template.as_ref().map(|t| (**t).clone())
+    }
+
+    pub fn get_tails(&self) -> &TailService {
+        &self.tails
+    }
+
+    pub fn lookup_query(&self, name: &str) -> Option<SavedQuery> {
+        self.queries.iter().find(|q| q.name == name).cloned()
+    }
+
     pub fn lookup_source(&self, key: &str) -> Option<LogSource> {
         let maybe_src = self
             .sources
@@ -51,4 +95,26 @@ impl ServerState {
             .find(|src| Self::extract_source_key(src) == key);
         maybe_src.map(|s| (*s).clone())
     }
+
+    /// Ids of the sources with an id matching one of the wildcard patterns (any id, if none are given)
+    /// and carrying all of the given labels. Label values may contain wildcards too.
+    pub fn select_sources(&self, patterns: &[&str], labels: &[(&str, &str)]) -> Vec<String> {
+        self.sources
+            .iter()
+            .filter(|src| {
+                let id = Self::extract_source_key(src);
+                patterns.is_empty() || patterns.iter().any(|p| util::wildcard_match(p, id))
+            })
+            .filter(|src| {
+                let source_labels = Self::extract_source_labels(src);
+                labels.iter().all(|(key, pattern)| {
+                    source_labels
+                        .get(*key)
+                        .map(|value| util::wildcard_match(pattern, value))
+                        .unwrap_or(false)
+                })
+            })
+            .map(|src| Self::extract_source_key(src).clone())
+            .collect()
+    }
 }
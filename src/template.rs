@@ -0,0 +1,232 @@
+use crate::data::{ApplicationError, StreamEntry};
+use chrono::format::{Item, StrftimeItems};
+use chrono::{TimeZone, Utc};
+use std::collections::BTreeMap;
+
+// timestamps without a format given in the placeholder
+const DEFAULT_TIMESTAMP_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.3f%:z";
+
+#[derive(Debug, Clone)]
+enum Part {
+    Literal(String),
+    Timestamp {
+        format: String,
+        timezone: Option<chrono_tz::Tz>, // UTC if None
+    },
+    Field(String),
+}
+
+/// Output of the text format like `{ts:%H:%M:%S%.3f} {source} [{level}] {message}`.
+///
+/// Placeholders are `ts` (or `timestamp`) with an optional time zone and strftime format
+/// like `{ts@Europe/Berlin:%H:%M:%S}`, `level` (or `loglevel`), `message`, `raw`, `source`,
+/// `file`, `line` and captures of the line pattern. `{{` and `}}` are literal braces.
+#[derive(Debug, Clone)]
+pub struct Template {
+    raw: String,
+    parts: Vec<Part>,
+}
+
+fn invalid(template: &str, reason: &str) -> ApplicationError {
+    ApplicationError::InvalidQuery(format!("Invalid template {}: {}", template, reason))
+}
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Template, ApplicationError> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut placeholder = String::new();
+                    let mut closed = false;
+                    for c in chars.by_ref() {
+                        if c == '}' {
+                            closed = true;
+                            break;
+                        }
+                        placeholder.push(c);
+                    }
+                    if !closed {
+                        return Err(invalid(template, "unclosed placeholder"));
+                    }
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Self::parse_placeholder(template, &placeholder)?);
+                }
+                '}' => return Err(invalid(template, "unmatched }")),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Template {
+            raw: template.to_string(),
+            parts,
+        })
+    }
+
+    fn parse_placeholder(template: &str, placeholder: &str) -> Result<Part, ApplicationError> {
+        let mut split = placeholder.splitn(2, ':');
+        let name = split.next().unwrap_or("").trim();
+        let format = split.next();
+        let mut split = name.splitn(2, '@');
+        let (name, timezone) = (split.next().unwrap_or(""), split.next());
+        match name {
+            "ts" | "timestamp" => {
+                let timezone = match timezone {
+                    Some(timezone) => Some(
+                        timezone
+                            .parse::<chrono_tz::Tz>()
+                            .map_err(|_| invalid(template, "unknown time zone"))?,
+                    ),
+                    None => None,
+                };
+                let format = format.unwrap_or(DEFAULT_TIMESTAMP_FORMAT);
+                if StrftimeItems::new(format).any(|item| item == Item::Error) {
+                    return Err(invalid(template, "invalid timestamp format"));
+                }
+                Ok(Part::Timestamp {
+                    format: format.to_string(),
+                    timezone,
+                })
+            }
+            "" => Err(invalid(template, "empty placeholder")),
+            _ if timezone.is_some() || format.is_some() => Err(invalid(
+                template,
+                "only timestamps take a time zone or format",
+            )),
+            "level" => Ok(Part::Field("loglevel".to_string())),
+            name => Ok(Part::Field(name.to_string())),
+        }
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.raw
+    }
+
+    /// The text of an entry, with `{ts}` rendered from the given timestamp instead of the entry's own,
+    /// which is missing for continuation lines. Placeholders of values the entry does not have are left empty.
+    pub fn render(&self, entry: &StreamEntry, timestamp: u128) -> String {
+        let origin = entry.origin.as_ref();
+        let mut text = String::with_capacity(entry.line.len() + 32);
+        for part in &self.parts {
+            match part {
+                Part::Literal(literal) => text.push_str(literal),
+                Part::Timestamp { format, timezone } => {
+                    let millis = timestamp as i64;
+                    let formatted = match timezone {
+                        Some(timezone) => timezone.timestamp_millis(millis).format(format),
+                        None => Utc.timestamp_millis(millis).format(format),
+                    };
+                    text.push_str(&formatted.to_string());
+                }
+                Part::Field(name) => match name.as_str() {
+                    "raw" => text.push_str(&entry.line),
+                    "source" => text.push_str(origin.map(|o| o.source.as_str()).unwrap_or("")),
+                    "file" => text.push_str(origin.map(|o| o.file.as_str()).unwrap_or("")),
+                    "line" => {
+                        if let Some(line) = origin.and_then(|o| o.line) {
+                            text.push_str(&line.to_string());
+                        }
+                    }
+                    name => text.push_str(entry.parsed_line.field(name).unwrap_or("")),
+                },
+            }
+        }
+        text
+    }
+}
+
+/// Templates of the text output, by source or for all entries.
+#[derive(Debug, Default)]
+pub struct Templates {
+    pub sources: BTreeMap<String, Template>,
+    pub default: Option<Template>,
+}
+
+impl Templates {
+    /// The template of an entry, if any. Markers keep their own text.
+    pub fn select(&self, entry: &StreamEntry) -> Option<&Template> {
+        if entry.marker.is_some() {
+            return None;
+        }
+        entry
+            .origin
+            .as_ref()
+            .and_then(|origin| self.sources.get(origin.source.as_str()))
+            .or(self.default.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::{fixtures, StreamEntry};
+    use crate::template::{Template, Templates};
+    use std::collections::BTreeMap;
+
+    fn entry() -> StreamEntry {
+        fixtures::entry("failed")
+    }
+
+    fn render(template: &str) -> String {
+        let e = entry();
+        Template::parse(template)
+            .unwrap()
+            .render(&e, e.parsed_line.timestamp + 5)
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            render("{ts:%H:%M:%S%.3f} {source} [{level}] {message}"),
+            "09:00:01.005 demo [ERROR] failed"
+        );
+        assert_eq!(
+            render("{timestamp@Europe/Berlin:%H:%M %Z} {{{thread}}} {file}:{line}"),
+            "10:00 CET {main} demo.log:3"
+        );
+        assert_eq!(render("{ts}"), "2019-01-01T09:00:01.005+00:00");
+        assert_eq!(
+            render("{unknown}|{raw}"),
+            "|2019-01-01 10:00:01 ERROR failed"
+        );
+    }
+
+    #[test]
+    fn test_invalid() {
+        assert!(Template::parse("{ts").is_err());
+        assert!(Template::parse("{ts} {ts").is_err());
+        assert!(Template::parse("{}").is_err());
+        assert!(Template::parse("level}").is_err());
+        assert!(Template::parse("{ts@Mars/Olympus}").is_err());
+        assert!(Template::parse("{ts:%Q}").is_err());
+        assert!(Template::parse("{message:%H}").is_err());
+    }
+
+    #[test]
+    fn test_select() {
+        let mut sources = BTreeMap::new();
+        sources.insert("demo".to_string(), Template::parse("{message}").unwrap());
+        let templates = Templates {
+            sources,
+            default: Some(Template::parse("{raw}").unwrap()),
+        };
+        let mut e = entry();
+        assert_eq!(templates.select(&e).unwrap().as_str(), "{message}");
+        e.origin = None;
+        assert_eq!(templates.select(&e).unwrap().as_str(), "{raw}");
+        assert!(Templates::default().select(&e).is_none());
+    }
+}
@@ -0,0 +1,164 @@
+use crate::data::ApplicationError;
+use crate::util;
+
+/// Requested time range, shared by the query parameters of all endpoints taking a range
+/// and the websocket subscription.
+/// The start is given by one of `from_ms`, `from` or `since`, the end by `to_ms` or `until`.
+#[derive(Deserialize, Debug, Default, PartialEq)]
+pub struct TimeRange {
+    pub from_ms: Option<i64>,
+    pub to_ms: Option<i64>,
+    pub from: Option<String>,  // epoch millis or RFC 3339 timestamp
+    pub until: Option<String>, // epoch millis or RFC 3339 timestamp
+    pub since: Option<String>, // duration before now, e.g. 15m
+}
+
+fn invalid_time(name: &str, value: &dyn std::fmt::Display) -> ApplicationError {
+    ApplicationError::InvalidQuery(format!("Invalid value for {}: {}", name, value))
+}
+
+impl TimeRange {
+    /// Resolve the range to epoch millis, the start defaults to the epoch and the end is open if not given.
+    pub fn resolve(&self) -> Result<(u128, Option<u128>), ApplicationError> {
+        let epoch_ms = |name: &str, value: i64| {
+            if value < 0 {
+                Err(invalid_time(name, &value))
+            } else {
+                Ok(value as u128)
+            }
+        };
+        let timestamp_ms = |name: &str, value: &str| {
+            util::parse_timestamp_ms(value).ok_or_else(|| invalid_time(name, &value))
+        };
+
+        let mut from = Vec::new();
+        if let Some(from_ms) = self.from_ms {
+            from.push(epoch_ms("from_ms", from_ms)?);
+        }
+        if let Some(value) = &self.from {
+            from.push(timestamp_ms("from", value)?);
+        }
+        if let Some(since) = &self.since {
+            let duration =
+                util::parse_duration_ms(since).ok_or_else(|| invalid_time("since", since))?;
+            from.push(util::now_ms().saturating_sub(duration));
+        }
+        let mut to = Vec::new();
+        if let Some(to_ms) = self.to_ms {
+            to.push(epoch_ms("to_ms", to_ms)?);
+        }
+        if let Some(value) = &self.until {
+            to.push(timestamp_ms("until", value)?);
+        }
+        if from.len() > 1 || to.len() > 1 {
+            return Err(ApplicationError::InvalidQuery(
+                "Only one of from_ms, from and since and one of to_ms and until may be given"
+                    .to_string(),
+            ));
+        }
+
+        let from_ms = from.pop().unwrap_or(0);
+        let to_ms = to.pop();
+        if let Some(to_ms) = to_ms {
+            if to_ms <= from_ms {
+                return Err(ApplicationError::InvalidQuery(format!(
+                    "End of time range {} is not after its start {}",
+                    to_ms, from_ms
+                )));
+            }
+        }
+        Ok((from_ms, to_ms))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::ApplicationError;
+    use crate::time_range::TimeRange;
+
+    #[test]
+    fn test_resolve() {
+        assert_eq!(TimeRange::default().resolve().unwrap(), (0, None));
+        let range = TimeRange {
+            from: Some("2019-01-01T10:00:00+01:00".to_string()),
+            to_ms: Some(1_546_336_800_000),
+            ..TimeRange::default()
+        };
+        assert_eq!(
+            range.resolve().unwrap(),
+            (1_546_333_200_000, Some(1_546_336_800_000))
+        );
+        let range = TimeRange {
+            since: Some("15m".to_string()),
+            ..TimeRange::default()
+        };
+        assert!(range.resolve().unwrap().0 > 0);
+    }
+
+    #[test]
+    fn test_inverted_range() {
+        for (from_ms, to_ms) in &[(2000, 1000), (1000, 1000)] {
+            let range = TimeRange {
+                from_ms: Some(*from_ms),
+                to_ms: Some(*to_ms),
+                ..TimeRange::default()
+            };
+            assert!(matches!(
+                range.resolve(),
+                Err(ApplicationError::InvalidQuery(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_conflicting_range() {
+        let ranges = vec![
+            TimeRange {
+                from_ms: Some(1000),
+                since: Some("1h".to_string()),
+                ..TimeRange::default()
+            },
+            TimeRange {
+                from: Some("1000".to_string()),
+                from_ms: Some(1000),
+                ..TimeRange::default()
+            },
+            TimeRange {
+                to_ms: Some(2000),
+                until: Some("2000".to_string()),
+                ..TimeRange::default()
+            },
+        ];
+        for range in ranges {
+            assert!(matches!(
+                range.resolve(),
+                Err(ApplicationError::InvalidQuery(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        let ranges = vec![
+            TimeRange {
+                from_ms: Some(-1),
+                ..TimeRange::default()
+            },
+            TimeRange {
+                until: Some("yesterday".to_string()),
+                ..TimeRange::default()
+            },
+            TimeRange {
+                // overflows the milliseconds
+                since: Some(format!("{}d", u128::MAX)),
+                ..TimeRange::default()
+            },
+        ];
+        for range in ranges {
+            assert!(matches!(
+                range.resolve(),
+                Err(ApplicationError::InvalidQuery(_))
+            ));
+        }
+    }
+}
@@ -1,5 +1,9 @@
+use chrono::DateTime;
 use chrono::NaiveDateTime;
+use regex::Regex;
+use std::convert::TryFrom;
 use std::time::SystemTime;
+use std::time::UNIX_EPOCH;
 
 pub fn system_time_to_date_time(t: SystemTime) -> NaiveDateTime {
     let (sec, nsec) = match t.duration_since(SystemTime::UNIX_EPOCH) {
@@ -17,3 +21,231 @@ pub fn system_time_to_date_time(t: SystemTime) -> NaiveDateTime {
     };
     NaiveDateTime::from_timestamp(sec, nsec)
 }
+
+/// Device and inode of a file, which identify it independent of its current name.
+#[cfg(unix)]
+pub fn file_identity(meta: &std::fs::Metadata) -> (u64, u64) {
+    use std::os::unix::fs::MetadataExt;
+    (meta.dev(), meta.ino())
+}
+
+#[cfg(not(unix))]
+pub fn file_identity(_meta: &std::fs::Metadata) -> (u64, u64) {
+    (0, 0)
+}
+
+/// Parse a duration like `500ms`, `30s`, `15m`, `2h` or `7d` into milliseconds.
+/// A number without unit is taken as milliseconds, durations beyond the range of milliseconds are invalid.
+pub fn parse_duration_ms(duration: &str) -> Option<u128> {
+    let duration = duration.trim();
+    let split_at = duration
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(duration.len());
+    let (value, unit) = duration.split_at(split_at);
+    let value: u128 = value.parse().ok()?;
+    let factor = match unit {
+        "" | "ms" => 1,
+        "s" => 1000,
+        "m" => 60 * 1000,
+        "h" => 60 * 60 * 1000,
+        "d" => 24 * 60 * 60 * 1000,
+        _ => return None,
+    };
+    value.checked_mul(factor)
+}
+
+/// Match text against a pattern, in which `*` stands for any sequence of characters
+/// and `?` for a single character.
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // position of the last `*` and the text position it was tried at
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some(c) if *c == '?' || *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                // let the last `*` match one more character
+                Some((star, star_t)) => {
+                    backtrack = Some((star, star_t + 1));
+                    p = star + 1;
+                    t = star_t + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+/// Parse a point in time given as epoch milliseconds or as RFC 3339 timestamp,
+/// like `2019-01-01T10:00:00+01:00`. Times before the epoch are rejected.
+pub fn parse_timestamp_ms(timestamp: &str) -> Option<u128> {
+    let timestamp = timestamp.trim();
+    if !timestamp.is_empty() && timestamp.bytes().all(|b| b.is_ascii_digit()) {
+        return timestamp.parse().ok();
+    }
+    // an unescaped '+' of the offset arrives as space in query strings
+    let datetime = DateTime::parse_from_rfc3339(timestamp)
+        .or_else(|_| DateTime::parse_from_rfc3339(&timestamp.replace(' ', "+")))
+        .ok()?;
+    let millis = datetime.timestamp_millis();
+    if millis < 0 {
+        None
+    } else {
+        Some(millis as u128)
+    }
+}
+
+/// Format epoch milliseconds as UTC time with the given chrono format.
+/// Times beyond the range of chrono are given as epoch milliseconds.
+pub fn format_timestamp_ms(timestamp: u128, format: &str) -> String {
+    let secs = i64::try_from(timestamp / 1000).ok();
+    let nanos = (timestamp % 1000) as u32 * 1_000_000;
+    match secs.and_then(|secs| NaiveDateTime::from_timestamp_opt(secs, nanos)) {
+        Some(datetime) => datetime.format(format).to_string(),
+        None => timestamp.to_string(),
+    }
+}
+
+/// Names of the captures of a grok line pattern, like `message` for `%{GREEDYDATA:message}`
+/// or `pid` for a named group `(?<pid>\d+)`, in the order of their occurrence.
+pub fn capture_names(line_pattern: &str) -> Vec<String> {
+    // escaped characters are consumed first, so `\(?<x>` is not taken for a named group
+    let capture = Regex::new(r"\\.|%\{\w+:(\w+)(:\w+)?\}|\(\?P?<(\w+)>").unwrap();
+    let mut names: Vec<String> = Vec::new();
+    for name in capture
+        .captures_iter(line_pattern)
+        .filter_map(|c| c.get(1).or_else(|| c.get(3)))
+        .map(|m| m.as_str().to_string())
+    {
+        if !names.contains(&name) {
+            names.push(name);
+        }
+    }
+    names
+}
+
+/// Current time in epoch milliseconds.
+pub fn now_ms() -> u128 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
+
+/// 64 bit FNV-1a hash with a final avalanche step, so all bits are usable for sampling.
+/// Unlike the std hashers it is stable across processes and releases.
+pub fn stable_hash(bytes: &[u8]) -> u64 {
+    let mut hash = bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    });
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+    hash ^ (hash >> 33)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::util::capture_names;
+    use crate::util::format_timestamp_ms;
+    use crate::util::parse_duration_ms;
+    use crate::util::parse_timestamp_ms;
+    use crate::util::stable_hash;
+    use crate::util::wildcard_match;
+
+    #[test]
+    fn test_parse_duration_ms() {
+        assert_eq!(parse_duration_ms("250"), Some(250));
+        assert_eq!(parse_duration_ms("250ms"), Some(250));
+        assert_eq!(parse_duration_ms("30s"), Some(30_000));
+        assert_eq!(parse_duration_ms("15m"), Some(900_000));
+        assert_eq!(parse_duration_ms("2h"), Some(7_200_000));
+        assert_eq!(parse_duration_ms("1d"), Some(86_400_000));
+        assert_eq!(parse_duration_ms("m"), None);
+        assert_eq!(parse_duration_ms("-5m"), None);
+        assert_eq!(parse_duration_ms("5 weeks"), None);
+        assert_eq!(parse_duration_ms(&format!("{}d", u128::MAX / 1000)), None);
+    }
+
+    #[test]
+    fn test_parse_timestamp_ms() {
+        assert_eq!(parse_timestamp_ms("1546326001000"), Some(1_546_326_001_000));
+        assert_eq!(
+            parse_timestamp_ms("2019-01-01T10:00:00+01:00"),
+            Some(1_546_333_200_000)
+        );
+        assert_eq!(
+            parse_timestamp_ms("2019-01-01T10:00:00 01:00"),
+            Some(1_546_333_200_000)
+        );
+        assert_eq!(
+            parse_timestamp_ms("2019-01-01T09:00:00.5Z"),
+            Some(1_546_333_200_500)
+        );
+        assert_eq!(parse_timestamp_ms("-1000"), None);
+        assert_eq!(parse_timestamp_ms("1969-12-31T23:59:59Z"), None);
+        assert_eq!(parse_timestamp_ms("2019-01-01"), None);
+        assert_eq!(parse_timestamp_ms(""), None);
+    }
+
+    #[test]
+    fn test_format_timestamp_ms() {
+        assert_eq!(
+            format_timestamp_ms(1_546_333_200_500, "%Y-%m-%dT%H:%M:%S%.3fZ"),
+            "2019-01-01T09:00:00.500Z"
+        );
+        assert_eq!(format_timestamp_ms(0, "%Y%m%dT%H%M%SZ"), "19700101T000000Z");
+        assert_eq!(
+            format_timestamp_ms(i64::MAX as u128, "%Y%m%dT%H%M%SZ"),
+            "9223372036854775807"
+        );
+    }
+
+    #[test]
+    fn test_capture_names() {
+        assert_eq!(
+            capture_names("%{TIMESTAMP_ISO8601:timestamp} [%{DATA:thread}] %{LOGLEVEL:loglevel} %{NUMBER:pid:int} %{GREEDYDATA:message} %{DATA:thread}"),
+            vec!["timestamp", "thread", "loglevel", "pid", "message"]
+        );
+        assert!(capture_names("%{GREEDYDATA}").is_empty());
+        assert_eq!(
+            capture_names(
+                r"%{TIMESTAMP_ISO8601:timestamp} \[(?<thread>[^\]]+)\] pid=(?P<pid>\d+) %{GREEDYDATA:message}"
+            ),
+            vec!["timestamp", "thread", "pid", "message"]
+        );
+        assert!(capture_names(r"\(?<x>) (?<=a)(?<!b)").is_empty());
+    }
+
+    #[test]
+    fn test_stable_hash() {
+        assert_eq!(stable_hash(b""), 0xefd0_1f60_ba99_2926);
+        assert_eq!(stable_hash(b"a"), 0x82a2_a958_a9be_ce5b);
+    }
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("billing-*", "billing-api"));
+        assert!(wildcard_match("billing-*", "billing-"));
+        assert!(wildcard_match("*-api", "billing-api"));
+        assert!(wildcard_match("b?lling*api", "billing-api"));
+        assert!(wildcard_match("*a*b*", "xaxxbx"));
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("web", "web"));
+        assert!(!wildcard_match("web", "web2"));
+        assert!(!wildcard_match("billing-*", "auth-api"));
+        assert!(!wildcard_match("*a*b", "xaxxbx"));
+        assert!(!wildcard_match("?", ""));
+    }
+}
@@ -0,0 +1,500 @@
+use crate::cursor::Cursor;
+use crate::data::{ApplicationError, LogQueryContext, Order, ParsedLine, StreamEntry};
+use crate::log_merge::LogMerge;
+use crate::logsource_svc::LogSourceService;
+use crate::sse::HEARTBEAT_INTERVAL;
+use crate::state::ServerState;
+use crate::time_range::TimeRange;
+use actix_codec::{Decoder, Encoder};
+use actix_http::error::PayloadError;
+use actix_http::ws::{Codec, Frame, Message};
+use bytes::{Bytes, BytesMut};
+use core::pin::Pin;
+use core::task::Context;
+use futures::future::Future;
+use futures::stream::{Stream, StreamExt};
+use futures::task::Poll;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Arc;
+
+/// Messages sent by the client as json text frames, distinguished by their `type`.
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ClientMessage {
+    /// Start a live tail, replacing the current one.
+    Subscribe {
+        #[serde(default)]
+        sources: Vec<String>, // id patterns, `*` and `?` as wildcards, all sources if empty
+        #[serde(default)]
+        labels: BTreeMap<String, String>,
+        loglevels: Option<Vec<String>>,
+        search: Option<String>,
+        #[serde(flatten)]
+        range: TimeRange, // as for the http endpoints, e.g. `since`
+        cursor: Option<String>, // resume after the position of an earlier message
+    },
+    /// Change the filters of the current tail, omitted filters are kept.
+    /// An empty list of loglevels or an empty search removes the respective filter.
+    UpdateFilter {
+        sources: Option<Vec<String>>,
+        labels: Option<BTreeMap<String, String>>,
+        loglevels: Option<Vec<String>>,
+        search: Option<String>,
+    },
+    Pause,
+    Resume,
+}
+
+/// Messages sent to the client as json text frames.
+#[derive(Serialize, Debug)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ServerMessage<'a> {
+    Line {
+        #[serde(skip_serializing_if = "Option::is_none")]
+        source: Option<&'a str>,
+        cursor: String,
+        line: &'a ParsedLine,
+    },
+    Error {
+        message: String,
+    },
+    End, // all selected sources are read, without any of them being watched
+}
+
+/// Filters applied to the entries of the tail within the session, so they can change without reopening sources.
+#[derive(Debug, Default)]
+struct LiveFilter {
+    loglevels: Option<Vec<String>>,
+    search: Option<String>, // substring of the raw line
+}
+
+impl LiveFilter {
+    fn update(&mut self, loglevels: Option<Vec<String>>, search: Option<String>) {
+        if let Some(loglevels) = loglevels {
+            self.loglevels = Some(loglevels).filter(|l| !l.is_empty());
+        }
+        if let Some(search) = search {
+            self.search = Some(search).filter(|s| !s.is_empty());
+        }
+    }
+
+    fn matches(&self, entry: &StreamEntry) -> bool {
+        let level_matches = match &self.loglevels {
+            Some(loglevels) => match &entry.parsed_line.loglevel {
+                Some(loglevel) => loglevels.iter().any(|l| l == loglevel),
+                None => false,
+            },
+            None => true,
+        };
+        let search_matches = match &self.search {
+            Some(search) => entry.line.contains(search.as_str()),
+            None => true,
+        };
+        level_matches && search_matches
+    }
+}
+
+/// Selected sources and time range of a subscription, needed to reopen the sources when the selection changes.
+struct Subscription {
+    sources: Vec<String>,
+    labels: BTreeMap<String, String>,
+    from_ms: u128,
+    to_ms: Option<u128>,
+}
+
+/// A websocket session streaming the merged lines of the subscribed sources, while reading the
+/// client's messages from the request payload. The selected sources are watched, pausing stops
+/// reading them, so no lines are lost until the session resumes.
+///
+/// The session drives `actix_http::ws::Codec` itself, as actix-web-actors is not a dependency yet.
+/// Once it is, framing, pings and close handling should move to its `WebsocketContext`, leaving
+/// only `handle_message` and the merged stream here.
+pub struct WsSession<P> {
+    payload: P,
+    state: ServerState,
+    codec: Codec,
+    read_buffer: BytesMut,
+    outgoing: VecDeque<Message>,
+    subscription: Option<Subscription>,
+    filter: LiveFilter,
+    merged: Option<LogMerge>,
+    cursor: Cursor,
+    paused: bool,
+    closing: bool,
+    ping_timer: Option<Pin<Box<dyn Future<Output = ()>>>>,
+}
+
+impl<P> WsSession<P>
+where
+    P: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    pub fn new(payload: P, state: ServerState) -> WsSession<P> {
+        WsSession {
+            payload,
+            state,
+            codec: Codec::new(),
+            read_buffer: BytesMut::new(),
+            outgoing: VecDeque::new(),
+            subscription: None,
+            filter: LiveFilter::default(),
+            merged: None,
+            cursor: Cursor::default(),
+            paused: false,
+            closing: false,
+            ping_timer: None,
+        }
+    }
+
+    fn send(&mut self, message: &ServerMessage) {
+        match serde_json::to_string(message) {
+            Ok(json) => self.outgoing.push_back(Message::Text(json)),
+            Err(e) => error!("Failed to convert websocket message to json: {}", e),
+        }
+    }
+
+    fn send_error(&mut self, e: ApplicationError) {
+        self.send(&ServerMessage::Error {
+            message: e.to_string(),
+        });
+    }
+
+    /// (Re)open the sources of the subscription, continuing after the entries already sent.
+    fn open(&mut self) -> Result<(), ApplicationError> {
+        let subscription = match &self.subscription {
+            Some(subscription) => subscription,
+            None => return Err(ApplicationError::InvalidQuery("Not subscribed".to_string())),
+        };
+        let patterns: Vec<&str> = subscription.sources.iter().map(String::as_str).collect();
+        let labels: Vec<(&str, &str)> = subscription
+            .labels
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+            .collect();
+        let ids = self.state.select_sources(&patterns, &labels);
+        debug!("Websocket tail of sources {:?}", ids);
+
+        // levels are filtered within the session, so a changed filter does not need to reopen the sources
+        let logfilter = Arc::new(LogQueryContext {
+            from_ms: subscription.from_ms,
+            to_ms: subscription.to_ms,
+            loglevels: None,
+            watch: Some(true),
+            after: Some(self.cursor.clone()),
+            before_lines: 0,
+            after_lines: 0,
+            dedupe: None,
+            order: Order::Asc,
+        });
+        let ids: Vec<&str> = ids.iter().map(String::as_str).collect();
+        let merged = LogSourceService::create_merged_stream(&ids, self.state.clone(), &logfilter)?;
+        self.merged = Some(merged);
+        Ok(())
+    }
+
+    fn handle_message(&mut self, message: ClientMessage) -> Result<(), ApplicationError> {
+        match message {
+            ClientMessage::Subscribe {
+                sources,
+                labels,
+                loglevels,
+                search,
+                range,
+                cursor,
+            } => {
+                let (from_ms, to_ms) = range.resolve()?;
+                self.cursor = match cursor {
+                    Some(cursor) => Cursor::decode(&cursor)?,
+                    None => Cursor::default(),
+                };
+                self.filter = LiveFilter::default();
+                self.filter.update(loglevels, search);
+                self.subscription = Some(Subscription {
+                    sources,
+                    labels,
+                    from_ms,
+                    to_ms,
+                });
+                self.open()
+            }
+            ClientMessage::UpdateFilter {
+                sources,
+                labels,
+                loglevels,
+                search,
+            } => {
+                let subscription = self
+                    .subscription
+                    .as_mut()
+                    .ok_or_else(|| ApplicationError::InvalidQuery("Not subscribed".to_string()))?;
+                self.filter.update(loglevels, search);
+                if sources.is_none() && labels.is_none() {
+                    return Ok(());
+                }
+                if let Some(sources) = sources {
+                    subscription.sources = sources;
+                }
+                if let Some(labels) = labels {
+                    subscription.labels = labels;
+                }
+                self.open()
+            }
+            ClientMessage::Pause => {
+                self.paused = true;
+                Ok(())
+            }
+            ClientMessage::Resume => {
+                self.paused = false;
+                Ok(())
+            }
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Frame) {
+        match frame {
+            Frame::Text(text) => {
+                let result = serde_json::from_slice::<ClientMessage>(&text)
+                    .map_err(|e| ApplicationError::InvalidQuery(format!("Invalid message: {}", e)))
+                    .and_then(|message| self.handle_message(message));
+                if let Err(e) = result {
+                    self.send_error(e);
+                }
+            }
+            Frame::Ping(data) => self.outgoing.push_back(Message::Pong(data)),
+            Frame::Pong(_) => {}
+            Frame::Close(reason) => {
+                self.outgoing.push_back(Message::Close(reason));
+                self.closing = true;
+            }
+            Frame::Binary(_) | Frame::Continuation(_) => self.send_error(
+                ApplicationError::InvalidQuery("Expected json text messages".to_string()),
+            ),
+        }
+    }
+
+    /// Read the client's frames that are available, returns false if the connection ended.
+    fn read_payload(&mut self, cx: &mut Context) -> bool {
+        loop {
+            match self.payload.poll_next_unpin(cx) {
+                Poll::Ready(Some(Ok(bytes))) => self.read_buffer.extend_from_slice(&bytes),
+                Poll::Ready(Some(Err(e))) => {
+                    debug!("Websocket payload error: {}", e);
+                    return false;
+                }
+                Poll::Ready(None) => return false,
+                Poll::Pending => break,
+            }
+        }
+        while !self.closing {
+            match self.codec.decode(&mut self.read_buffer) {
+                Ok(Some(frame)) => self.handle_frame(frame),
+                Ok(None) => break,
+                Err(e) => {
+                    debug!("Websocket protocol error: {}", e);
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    fn encode(&mut self, message: Message) -> Poll<Option<Result<Bytes, ApplicationError>>> {
+        self.ping_timer = None;
+        let mut frame = BytesMut::new();
+        match self.codec.encode(message, &mut frame) {
+            Ok(()) => Poll::Ready(Some(Ok(frame.freeze()))),
+            Err(e) => {
+                error!("Failed to encode websocket message: {}", e);
+                Poll::Ready(None)
+            }
+        }
+    }
+}
+
+impl<P> Stream for WsSession<P>
+where
+    P: Stream<Item = Result<Bytes, PayloadError>> + Unpin,
+{
+    type Item = Result<Bytes, ApplicationError>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(message) = self.outgoing.pop_front() {
+                return self.encode(message);
+            }
+            if self.closing || !self.read_payload(cx) {
+                return Poll::Ready(None);
+            }
+            if !self.outgoing.is_empty() {
+                continue;
+            }
+            if self.paused {
+                break;
+            }
+            let polled = match &mut self.merged {
+                Some(merged) => merged.poll_next_unpin(cx),
+                None => break,
+            };
+            match polled {
+                Poll::Ready(Some(entry)) => {
+                    if let Some(source_cursor) = &entry.cursor {
+                        self.cursor.advance(source_cursor);
+                    }
+                    if self.filter.matches(&entry) {
+                        let source = entry.origin.as_ref().map(|o| o.source.as_str());
+                        let cursor = self.cursor.encode();
+                        self.send(&ServerMessage::Line {
+                            source,
+                            cursor,
+                            line: &entry.parsed_line,
+                        });
+                    }
+                }
+                Poll::Ready(None) => {
+                    self.merged = None;
+                    self.send(&ServerMessage::End);
+                }
+                Poll::Pending => break,
+            }
+        }
+
+        // keep idle connections open through proxies
+        let timer = self
+            .ping_timer
+            .get_or_insert_with(|| Box::pin(async_std::task::sleep(HEARTBEAT_INTERVAL)));
+        if timer.as_mut().poll(cx).is_ready() {
+            return self.encode(Message::Ping(Bytes::new()));
+        }
+        Poll::Pending
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::data::LogSourceBuilder;
+    use crate::state::ServerState;
+    use crate::time_range::TimeRange;
+    use crate::websocket::{ClientMessage, WsSession};
+    use actix_codec::{Decoder, Encoder};
+    use actix_http::ws::{Codec, Frame, Message};
+    use async_std::task;
+    use bytes::{Bytes, BytesMut};
+    use futures::channel::mpsc;
+    use futures::future::FutureExt;
+    use futures::stream::StreamExt;
+
+    fn demo_state() -> ServerState {
+        let settings = crate::cfg::read_config(&Some("tests/integrationtests.yml")).unwrap();
+        let mut grok = grok::Grok::default();
+        let sources = settings
+            .get_array("sources")
+            .unwrap()
+            .iter()
+            .map(|v| LogSourceBuilder::create(v, &mut grok).unwrap())
+            .collect();
+        ServerState::new(sources, grok)
+    }
+
+    fn client_frame(json: &str) -> Bytes {
+        let mut frame = BytesMut::new();
+        Codec::new()
+            .client_mode()
+            .encode(Message::Text(json.to_string()), &mut frame)
+            .unwrap();
+        frame.freeze()
+    }
+
+    fn server_message(frame: Bytes) -> serde_json::Value {
+        let mut buffer = BytesMut::from(&frame[..]);
+        match Codec::new().client_mode().decode(&mut buffer).unwrap() {
+            Some(Frame::Text(text)) => serde_json::from_slice(&text).unwrap(),
+            f => panic!("unexpected frame {:?}", f),
+        }
+    }
+
+    #[test]
+    fn test_parse_client_messages() {
+        let message: ClientMessage =
+            serde_json::from_str(r#"{"type":"update_filter","loglevels":["ERROR"]}"#).unwrap();
+        assert_eq!(
+            message,
+            ClientMessage::UpdateFilter {
+                sources: None,
+                labels: None,
+                loglevels: Some(vec!["ERROR".to_string()]),
+                search: None,
+            }
+        );
+        let message: ClientMessage =
+            serde_json::from_str(r#"{"type":"subscribe","since":"15m","to_ms":1000}"#).unwrap();
+        match message {
+            ClientMessage::Subscribe { range, .. } => assert_eq!(
+                range,
+                TimeRange {
+                    since: Some("15m".to_string()),
+                    to_ms: Some(1000),
+                    ..TimeRange::default()
+                }
+            ),
+            message => panic!("Unexpected message {:?}", message),
+        }
+        let message: ClientMessage = serde_json::from_str(r#"{"type":"pause"}"#).unwrap();
+        assert_eq!(message, ClientMessage::Pause);
+        assert!(serde_json::from_str::<ClientMessage>(r#"{"type":"unknown"}"#).is_err());
+    }
+
+    #[test]
+    fn test_change_filters_mid_stream() {
+        let (client, payload) = mpsc::unbounded();
+        let mut session = WsSession::new(payload, demo_state());
+        let mut next_message = |message: Option<&str>| {
+            if let Some(message) = message {
+                client.unbounded_send(Ok(client_frame(message))).unwrap();
+            }
+            let frame = task::block_on(session.next()).unwrap().unwrap();
+            let json = server_message(frame);
+            (json["type"].clone(), json["line"]["message"].clone())
+        };
+
+        let subscribe = r#"{"type":"subscribe","sources":["it*"],"loglevels":["ERROR"]}"#;
+        assert_eq!(
+            next_message(Some(subscribe)),
+            ("line".into(), "demo2line1".into())
+        );
+
+        let update = r#"{"type":"update_filter","loglevels":["DEBUG"]}"#;
+        assert_eq!(
+            next_message(Some(update)),
+            ("line".into(), "demo2line2".into())
+        );
+
+        // reopening the sources continues after the last sent line
+        let update = r#"{"type":"update_filter","sources":["itest"],"search":"demo1"}"#;
+        assert_eq!(
+            next_message(Some(update)),
+            ("line".into(), "demo1line2".into())
+        );
+
+        let invalid = r#"{"type":"subscribe","from":"yesterday"}"#;
+        assert_eq!(next_message(Some(invalid)).0, "error");
+    }
+
+    #[test]
+    fn test_pause_and_resume() {
+        let (client, payload) = mpsc::unbounded();
+        let mut session = WsSession::new(payload, demo_state());
+        let subscribe = r#"{"type":"subscribe","sources":["itest"]}"#;
+        client.unbounded_send(Ok(client_frame(subscribe))).unwrap();
+        assert!(task::block_on(session.next()).is_some());
+
+        client
+            .unbounded_send(Ok(client_frame(r#"{"type":"pause"}"#)))
+            .unwrap();
+        assert!(session.next().now_or_never().is_none());
+
+        client
+            .unbounded_send(Ok(client_frame(r#"{"type":"resume"}"#)))
+            .unwrap();
+        let frame = task::block_on(session.next()).unwrap().unwrap();
+        assert_eq!(server_message(frame)["line"]["message"], "demo2line2");
+    }
+}